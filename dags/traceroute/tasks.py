@@ -2,6 +2,7 @@ from airflow.contrib.operators.kubernetes_pod_operator import KubernetesPodOpera
 from kubernetes.client import models as k8s
 import os
 import json
+import shlex
 
 DATA_DIR = '/opt/data'
 SCANNER_IMAGE = "harbor.freemre.com/library/scanner:v5"
@@ -153,9 +154,10 @@ class Trace2LinkKubernetesPodOperator(KubernetesPodOperator):
         self.cmds = ["/bin/sh", "-c"]
 
         run_id = context['dag_run'].run_id
-        task_dir = os.path.join(DATA_DIR, run_id)
+        # quoted, the run_id is user-supplied for manual runs
+        task_dir = shlex.quote(os.path.join(DATA_DIR, run_id))
         self.arguments = [
-            "ls %s/*/ -d | while read l; do trace2link $(ls $l/*.warts) >$l/traceroute.links; done" % (
+            "for l in %s/*/; do trace2link -j $(nproc) -p \"$l\" \"$l\"*.warts || exit 1; done" % (
                 task_dir
             )
         ]
@@ -171,9 +173,9 @@ class LinkMergeKubernetesPodOperator(KubernetesPodOperator):
         self.cmds = ["/bin/sh", "-c"]
 
         run_id = context['dag_run'].run_id
-        task_dir = os.path.join(DATA_DIR, run_id)
+        task_dir = shlex.quote(os.path.join(DATA_DIR, run_id))
         self.arguments = [
-            "linkmerge $(ls %s/*/traceroute.links) >%s/traceroute.links" % (task_dir, task_dir)
+            "linkmerge %s/*/traceroute.links >%s/traceroute.links" % (task_dir, task_dir)
         ]
         super().execute(context)
 
@@ -191,7 +193,7 @@ class DealiasKubernetesPodOperator(KubernetesPodOperator):
         self.cmds = ["/bin/sh", "-c"]
 
         run_id = context['dag_run'].run_id
-        task_dir = os.path.join(DATA_DIR, run_id)
+        task_dir = shlex.quote(os.path.join(DATA_DIR, run_id))
         iface_filepath = "%s/traceroute.ifaces" % (task_dir)
         self.arguments = [
             "link2iface %s/traceroute.links | sort >%s && " % (task_dir, iface_filepath) +
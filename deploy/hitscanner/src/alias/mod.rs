@@ -0,0 +1,207 @@
+// Router-level aliases of interface addresses, see trace2mat --aliases
+//   - alias file: the aliases of the dealias step of the DAG (iffinder), one
+//     pair per line, e.g.
+//     10.0.0.1 10.0.0.5
+//     a line may list more addresses of the same router, # starts a comment
+//   - Aliases: the routers of an alias file, the alias pairs collapsed with
+//     union-find, each router named by its lowest address, its router ID;
+//     an address without aliases is a router of its own
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::path::Path;
+
+use crate::compress;
+use crate::error::{Format, InputError};
+
+// union-find over address indexes, union by size with path halving
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn add(&mut self) -> usize {
+        self.parent.push(self.parent.len());
+        self.size.push(1);
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+#[derive(Default)]
+pub struct Aliases {
+    router: HashMap<IpAddr, IpAddr>,       // address -> router ID
+    members: HashMap<IpAddr, Vec<IpAddr>>, // router ID -> its addresses, sorted
+}
+
+impl Aliases {
+    pub fn read(path: &Path) -> Result<Self, InputError> {
+        let name = path.display().to_string();
+        let file = compress::open(path)
+            .map_err(|e| InputError::new(Format::Alias, 0, &e.to_string()).in_file(&name))?;
+        let mut uf = UnionFind {
+            parent: Vec::new(),
+            size: Vec::new(),
+        };
+        let mut addrs: Vec<IpAddr> = Vec::new();
+        let mut index: HashMap<IpAddr, usize> = HashMap::new();
+        for (i, line) in file.lines().enumerate() {
+            let err = |e: &str| InputError::new(Format::Alias, i + 1, e).in_file(&name);
+            let line = line.map_err(|e| err(&e.to_string()))?;
+            let fields: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let mut set = Vec::new();
+            for f in &fields {
+                let a: IpAddr = f
+                    .parse()
+                    .map_err(|_| err(&format!("not an address: {}", f)).with_raw(&line))?;
+                set.push(a);
+            }
+            if set.iter().any(|a| a.is_ipv4() != set[0].is_ipv4()) {
+                return Err(err("IPv4 and IPv6 addresses can't be aliases").with_raw(&line));
+            }
+            let first = *index.entry(set[0]).or_insert_with(|| {
+                addrs.push(set[0]);
+                uf.add()
+            });
+            for a in &set[1..] {
+                let j = *index.entry(*a).or_insert_with(|| {
+                    addrs.push(*a);
+                    uf.add()
+                });
+                uf.union(first, j);
+            }
+        }
+
+        let mut sets: HashMap<usize, Vec<IpAddr>> = HashMap::new();
+        for (i, a) in addrs.iter().enumerate() {
+            sets.entry(uf.find(i)).or_default().push(*a);
+        }
+        let mut aliases = Aliases::default();
+        for (_, mut members) in sets {
+            members.sort();
+            let id = members[0];
+            for a in &members {
+                aliases.router.insert(*a, id);
+            }
+            aliases.members.insert(id, members);
+        }
+        Ok(aliases)
+    }
+
+    // the router ID of an address, the address itself without aliases
+    pub fn router(&self, addr: IpAddr) -> IpAddr {
+        self.router.get(&addr).copied().unwrap_or(addr)
+    }
+
+    // the addresses of a router, sorted
+    pub fn members(&self, router: IpAddr) -> Vec<IpAddr> {
+        self.members.get(&router).cloned().unwrap_or_else(|| vec![router])
+    }
+
+    // the routers with more than one address
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(test: &str, text: &str) -> Result<Aliases, InputError> {
+        let path = std::env::temp_dir().join(format!("hitscanner-aliases-{}-{}", test, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let aliases = Aliases::read(&path);
+        std::fs::remove_file(&path).unwrap();
+        aliases
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn union_find_merges_chains() {
+        let mut uf = UnionFind {
+            parent: Vec::new(),
+            size: Vec::new(),
+        };
+        let n: Vec<usize> = (0..6).map(|_| uf.add()).collect();
+        uf.union(n[0], n[1]);
+        uf.union(n[2], n[3]);
+        assert_ne!(uf.find(n[1]), uf.find(n[2]));
+        uf.union(n[3], n[1]);
+        let root = uf.find(n[0]);
+        assert!(n[..4].iter().all(|&i| uf.find(i) == root));
+        assert_eq!(uf.size[root], 4);
+        assert_ne!(uf.find(n[4]), root);
+        assert_ne!(uf.find(n[4]), uf.find(n[5]));
+    }
+
+    #[test]
+    fn pairs_collapse_into_routers() {
+        let aliases = read(
+            "routers",
+            "# iffinder\n\
+             10.0.0.7 10.0.0.5\n\
+             10.0.0.9 10.0.0.2 # a comment\n\
+             \n\
+             10.0.0.5 10.0.0.2\n\
+             10.0.1.1 10.0.1.3 10.0.1.2\n",
+        )
+        .unwrap();
+        assert_eq!(aliases.len(), 2);
+        assert_eq!(aliases.router(ip("10.0.0.7")), ip("10.0.0.2"));
+        assert_eq!(
+            aliases.members(ip("10.0.0.2")),
+            ["10.0.0.2", "10.0.0.5", "10.0.0.7", "10.0.0.9"].map(ip)
+        );
+        assert_eq!(aliases.router(ip("10.0.1.3")), ip("10.0.1.1"));
+        // without aliases, a router of its own
+        assert_eq!(aliases.router(ip("10.0.0.3")), ip("10.0.0.3"));
+        assert_eq!(aliases.members(ip("10.0.0.3")), [ip("10.0.0.3")]);
+    }
+
+    #[test]
+    fn ipv6_aliases() {
+        let aliases = read("ipv6", "2001:db8::9 2001:db8::1\n").unwrap();
+        assert_eq!(aliases.router(ip("2001:db8::9")), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        let e = read("bad", "10.0.0.1 10.0.0.2\n10.0.0.3 router1\n").err().unwrap();
+        assert_eq!((e.line, e.msg.as_str()), (2, "not an address: router1"));
+        assert_eq!(e.raw.as_deref(), Some("10.0.0.3 router1"));
+        let e = read("mixed", "10.0.0.1 2001:db8::1\n").err().unwrap();
+        assert_eq!((e.line, e.msg.as_str()), (1, "IPv4 and IPv6 addresses can't be aliases"));
+    }
+}
@@ -0,0 +1,311 @@
+// Compressed input and output shared by all binaries
+//   - Codec: none, gzip, zstd, xz or bz2
+//     - inputs are detected by their magic bytes, not by the file extension
+//     - outputs use the codec given on the command line, e.g. --compress=zstd
+//   - open: a decompressed reader of a file, "-" for STDIN
+//   - create: a compressing writer to a file, "-" for STDOUT
+//   - create_atomic: the same, but written to a temporary file next to it and
+//     renamed on finish, so readers never see a partial file
+// All codecs are built in, no external gzip/zstd processes are spawned.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bzip2::bufread::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use flate2::bufread::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xz2::bufread::XzDecoder;
+use xz2::write::XzEncoder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Plain,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Codec {
+    // from the first bytes of a stream
+    pub fn detect(magic: &[u8]) -> Codec {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Codec::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Codec::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Codec::Xz
+        } else if magic.starts_with(b"BZh") {
+            Codec::Bzip2
+        } else {
+            Codec::Plain
+        }
+    }
+
+    // file name suffix of the compressed output, e.g. ".gz"
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Codec::Plain => "",
+            Codec::Gzip => ".gz",
+            Codec::Zstd => ".zst",
+            Codec::Xz => ".xz",
+            Codec::Bzip2 => ".bz2",
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Codec::Plain),
+            "gzip" | "gz" => Ok(Codec::Gzip),
+            "zstd" | "zst" => Ok(Codec::Zstd),
+            "xz" => Ok(Codec::Xz),
+            "bzip2" | "bz2" => Ok(Codec::Bzip2),
+            _ => Err(format!("unknown codec {}, use none, gzip, zstd, xz or bz2", s)),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Codec::Plain => "none",
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Xz => "xz",
+            Codec::Bzip2 => "bz2",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// decompress `read` if it starts with the magic bytes of a codec
+pub fn reader(read: Box<dyn Read>) -> io::Result<Box<dyn BufRead>> {
+    let mut read = BufReader::new(read);
+    let codec = Codec::detect(read.fill_buf()?);
+    Ok(match codec {
+        Codec::Plain => Box::new(read),
+        // the multi-member decoders also read concatenated files, e.g. cat a.gz b.gz
+        Codec::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(read))),
+        Codec::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(read)?)),
+        Codec::Xz => Box::new(BufReader::new(XzDecoder::new_multi_decoder(read))),
+        Codec::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(read))),
+    })
+}
+
+// "-" for STDIN
+pub fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    if path.as_os_str() == "-" {
+        reader(Box::new(io::stdin()))
+    } else {
+        reader(Box::new(File::open(path)?))
+    }
+}
+
+enum Encoder {
+    Plain(BufWriter<Box<dyn Write>>),
+    Gzip(GzEncoder<BufWriter<Box<dyn Write>>>),
+    Zstd(zstd::Encoder<'static, BufWriter<Box<dyn Write>>>),
+    Xz(XzEncoder<BufWriter<Box<dyn Write>>>),
+    Bzip2(BzEncoder<BufWriter<Box<dyn Write>>>),
+}
+
+// A compressing writer, call finish to see the errors of the last write.
+// Dropping it also finishes the stream, but ignores the errors, and removes
+// the temporary file of create_atomic, as does a finish that fails.
+pub struct Writer {
+    enc: Encoder,
+    rename: Option<(PathBuf, PathBuf)>, // temporary and final path of create_atomic
+}
+
+impl Writer {
+    pub fn new(write: Box<dyn Write>, codec: Codec) -> io::Result<Self> {
+        let w = BufWriter::new(write);
+        let enc = match codec {
+            Codec::Plain => Encoder::Plain(w),
+            Codec::Gzip => Encoder::Gzip(GzEncoder::new(w, flate2::Compression::default())),
+            Codec::Zstd => Encoder::Zstd(zstd::Encoder::new(w, 0)?),
+            Codec::Xz => Encoder::Xz(XzEncoder::new(w, 6)),
+            Codec::Bzip2 => Encoder::Bzip2(BzEncoder::new(w, bzip2::Compression::default())),
+        };
+        Ok(Writer { enc, rename: None })
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        match &mut self.enc {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(w) => w.try_finish().and_then(|_| w.get_mut().flush()),
+            Encoder::Zstd(w) => w.do_finish().and_then(|_| w.get_mut().flush()),
+            Encoder::Xz(w) => w.try_finish().and_then(|_| w.get_mut().flush()),
+            Encoder::Bzip2(w) => w.try_finish().and_then(|_| w.get_mut().flush()),
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.try_finish()?;
+        match self.rename.take() {
+            Some((tmp, path)) => std::fs::rename(&tmp, path).inspect_err(|_| {
+                let _ = std::fs::remove_file(&tmp);
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.enc {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(w) => w.write(buf),
+            Encoder::Zstd(w) => w.write(buf),
+            Encoder::Xz(w) => w.write(buf),
+            Encoder::Bzip2(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.enc {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(w) => w.flush(),
+            Encoder::Zstd(w) => w.flush(),
+            Encoder::Xz(w) => w.flush(),
+            Encoder::Bzip2(w) => w.flush(),
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+        if let Some((tmp, _)) = self.rename.take() {
+            let _ = std::fs::remove_file(tmp);
+        }
+    }
+}
+
+// "-" for STDOUT
+pub fn create(path: &Path, codec: Codec) -> io::Result<Writer> {
+    if path.as_os_str() == "-" {
+        Writer::new(Box::new(io::stdout()), codec)
+    } else {
+        Writer::new(Box::new(File::create(path)?), codec)
+    }
+}
+
+// e.g. out/.rows.csv.1234.tmp for out/rows.csv, in the same directory so the
+// rename can't cross file systems, "-" for STDOUT
+pub fn create_atomic(path: &Path, codec: Codec) -> io::Result<Writer> {
+    if path.as_os_str() == "-" {
+        return create(path, codec);
+    }
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?;
+    let mut tmp = std::ffi::OsString::from(".");
+    tmp.push(name);
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = path.with_file_name(tmp);
+    let mut w = Writer::new(Box::new(File::create(&tmp)?), codec)?;
+    w.rename = Some((tmp, path.to_path_buf()));
+    Ok(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODECS: [Codec; 5] = [Codec::Plain, Codec::Gzip, Codec::Zstd, Codec::Xz, Codec::Bzip2];
+
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hitscanner-compress-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn read_all(path: &Path) -> String {
+        let mut s = String::new();
+        open(path).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn codec_names() {
+        for c in CODECS {
+            assert_eq!(c.to_string().parse::<Codec>(), Ok(c));
+        }
+        assert_eq!("gz".parse::<Codec>(), Ok(Codec::Gzip));
+        assert_eq!(Codec::Zstd.suffix(), ".zst");
+        assert!("lz4".parse::<Codec>().is_err());
+    }
+
+    #[test]
+    fn round_trip_detected_by_content() {
+        let dir = scratch("round-trip");
+        for c in CODECS {
+            // no suffix, the codec is told by the magic bytes
+            let path = dir.join(c.to_string());
+            let mut w = create(&path, c).unwrap();
+            write!(w, "10.0.0.1\n10.0.0.2\n").unwrap();
+            w.finish().unwrap();
+            let mut magic = [0u8; 6];
+            let n = File::open(&path).unwrap().read(&mut magic).unwrap();
+            assert_eq!(Codec::detect(&magic[..n]), c);
+            assert_eq!(read_all(&path), "10.0.0.1\n10.0.0.2\n", "{}", c);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let dir = scratch("members");
+        for c in [Codec::Gzip, Codec::Zstd, Codec::Xz, Codec::Bzip2] {
+            let mut bytes = Vec::new();
+            for part in ["a\n", "b\n"] {
+                let path = dir.join(part.trim());
+                let mut w = create(&path, c).unwrap();
+                w.write_all(part.as_bytes()).unwrap();
+                w.finish().unwrap();
+                bytes.extend(std::fs::read(&path).unwrap());
+            }
+            let path = dir.join("ab");
+            std::fs::write(&path, bytes).unwrap();
+            assert_eq!(read_all(&path), "a\nb\n", "{}", c);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn atomic_output_appears_on_finish() {
+        let dir = scratch("atomic");
+        let path = dir.join("rows.csv.gz");
+        let mut w = create_atomic(&path, Codec::Gzip).unwrap();
+        w.write_all(b"0,10.0.0.1\n").unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        w.finish().unwrap();
+        assert_eq!(read_all(&path), "0,10.0.0.1\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // a writer dropped without finish leaves the old file as it was, and no temporary one
+        let mut w = create_atomic(&path, Codec::Gzip).unwrap();
+        w.write_all(b"partial").unwrap();
+        drop(w);
+        assert_eq!(read_all(&path), "0,10.0.0.1\n");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        // nor does a failed rename, onto a directory
+        let busy = dir.join("busy");
+        std::fs::create_dir(&busy).unwrap();
+        let mut w = create_atomic(&busy, Codec::Plain).unwrap();
+        w.write_all(b"0,10.0.0.1\n").unwrap();
+        assert!(w.finish().is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
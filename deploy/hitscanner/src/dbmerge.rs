@@ -1,18 +1,27 @@
 // dbmerge -- merge a number of .db geoIPDB files into a single one
 // =============================================================================
 // USAGE: dbmerge [dot_db_file]
-// INPUT: a batch of .db geoIPDB files, with the following format:
+// INPUT: a batch of .db geoIPDB files, plain or gzip/zstd/xz/bz2 compressed, with the following format:
 //            "3758095872","3758096127","SG","Singapore","Singapore","Singapore","Marina Bay Sands Pte Ltd","marinabaysands.com"
 //        a valid .db file should satisfy:
 //             1. Intervals don't overlap
 //             2. Lines are sorted by Intervals
 // OUTPUT: .db file with only country code annotation, e.g.:
 //         3758096128,3758096383,0,"AU",1,"AU",2,"AU"
+//         where 0, 1, 2 are the indexes of the input files, in the order given
+//         with -m, a sidecar manifest of the inputs, one index,name line each,
+//         for trace2mat to name and count the signature digits
 // NOTE:   the algorithm uses [a,b), i.e. left-closed and right-open interval
 //         while .db files use [a,b-1], i.e. closed interval
+// NOTE:   IPv4 and IPv6 .db files are both supported (u128 ticks), but must not be mixed;
+//         the last IPv6 address (2^128-1) can't be represented as an open end and is dropped
 
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{read_line, Format, InputError, LineError, OnError, Rejects};
+use hitscanner::iputils::DbManifest;
+
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
 use std::result::Result;
 
 const HELP: &str = "\
@@ -20,14 +29,26 @@ Usage: dbmerge [OPTIONS] <files>
 
 OPTIONS:
 -s   split the ranges even if the country codes are the same
+-m   write the manifest of the inputs to this file, e.g. merged.db.manifest
+--on-error    skip|fail|quarantine malformed lines, default fail
+--quarantine  file of the quarantined lines, default dbmerge.rejected
+--compress    none|gzip|zstd|xz|bz2 compression of the output, default none
 ";
 
 #[allow(dead_code)]
 struct AppArgs {
     split: bool,
+    manifest: Option<PathBuf>,
+    on_error: OnError,
+    quarantine: PathBuf,
+    codec: Codec,
     inputs: Vec<std::ffi::OsString>,
 }
 
+fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
+    Ok(s.into())
+}
+
 fn get_option() -> Result<AppArgs, pico_args::Error> {
     let mut pargs = pico_args::Arguments::from_env();
 
@@ -39,6 +60,12 @@ fn get_option() -> Result<AppArgs, pico_args::Error> {
 
     let args = AppArgs {
         split: pargs.contains(["-s", "--split"]),
+        manifest: pargs.opt_value_from_os_str(["-m", "--manifest"], parse_path)?,
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_str("--quarantine")?
+            .unwrap_or(PathBuf::from("dbmerge.rejected")),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
         inputs: pargs.finish(),
     };
 
@@ -52,32 +79,92 @@ fn get_option() -> Result<AppArgs, pico_args::Error> {
 
 #[derive(Debug)]
 struct Range {
-    a: u64,
-    b: u64,
+    a: u128,
+    b: u128,
     g: String,
 }
 
-fn parse_line(line: &str) -> Option<Range> {
-    if line.is_empty() {
-        return None;
-    }
+fn parse_line(line: &str) -> Result<Range, String> {
     let fields: Vec<&str> = line.trim().split(',').collect();
+    if fields.len() < 3 {
+        return Err(format!("expected at least 3 fields, got {}", fields.len()));
+    }
     let mut g = fields[2].replace("\"", "").to_string();
     if g.is_empty() {
         g = "-".to_string();
     }
 
-    let a = fields[0].replace("\"", "").parse::<u64>().unwrap();
-    let b = fields[1].replace("\"", "").parse::<u64>().unwrap();
-    Some(Range {
+    let num = |f: &str| {
+        f.replace("\"", "")
+            .parse::<u128>()
+            .map_err(|_| format!("not an address number: {}", f))
+    };
+    let a = num(fields[0])?;
+    let b = num(fields[1])?;
+    if a > b {
+        return Err(format!("empty range {}-{}", a, b));
+    }
+    Ok(Range {
         a,
-        b: b + 1, // the algorithm uses [a,b), i.e. left-closed and right-open interval
+        b: b.saturating_add(1), // the algorithm uses [a,b), i.e. left-closed and right-open interval
         g,
     })
 }
 
-fn min_front(tl: &Vec<Option<Range>>, il: &Vec<usize>) -> (u64, isize, usize) {
-    let mut n = u64::MAX;
+// a rejected line, the merge stops with --on-error fail or if it can't be quarantined
+fn reject_or_exit(rejects: &mut Rejects, e: &InputError) {
+    if let Err(e) = rejects.reject(e) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+}
+
+// the next valid range of a .db file, None at the end of file
+// ranges overlapping the previous one break the merge and are rejected
+fn next_range(
+    reader: &mut Box<dyn BufRead>,
+    name: &str,
+    lineno: &mut usize,
+    end: &mut u128,
+    rejects: &mut Rejects,
+) -> Option<Range> {
+    loop {
+        let r = read_line(reader)?;
+        *lineno += 1;
+        let line = match r {
+            Ok(l) => l,
+            Err(e) => {
+                reject_or_exit(rejects, &e.reject(Format::GeoDb, *lineno, name));
+                match e {
+                    LineError::Utf8(_) => continue,
+                    LineError::Io(_) => return None,
+                }
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let r = match parse_line(&line) {
+            Ok(r) if r.a < *end => Err("range overlaps the previous one, or is out of order".to_string()),
+            r => r,
+        };
+        match r {
+            Ok(r) => {
+                *end = r.b;
+                return Some(r);
+            }
+            Err(e) => reject_or_exit(
+                rejects,
+                &InputError::new(Format::GeoDb, *lineno, &e)
+                    .in_file(name)
+                    .with_raw(line.trim_end()),
+            ),
+        }
+    }
+}
+
+fn min_front(tl: &Vec<Option<Range>>, il: &Vec<usize>) -> (u128, isize, usize) {
+    let mut n = u128::MAX;
     let mut i = -1;
     let mut j = 0;
 
@@ -85,7 +172,7 @@ fn min_front(tl: &Vec<Option<Range>>, il: &Vec<usize>) -> (u64, isize, usize) {
         let jj = il[ii];
         if let Some(range) = l {
             let front = if jj % 2 == 0 { range.a } else { range.b };
-            if front < n || (front == n && jj % 2 == 0) {
+            if i == -1 || front < n || (front == n && jj % 2 == 0) {
                 // jj%2 == 0 means the tick is the right end of the interval, which is open and thus smaller than closed n
                 n = front;
                 i = ii as isize;
@@ -101,17 +188,23 @@ fn min_front(tl: &Vec<Option<Range>>, il: &Vec<usize>) -> (u64, isize, usize) {
 // To find the next tick, it scans the top line of each .db file and selects the smallest value.
 // The interval annotation is determined by combining current annotations from each .db file.
 // To track the current annotation for each .db file, the tick index's parity is used, corresponding to interval "entry" and "exit" events.
-fn merge_ticks(ll: &mut Vec<BufReader<File>>, split: bool) {
+fn merge_ticks(
+    ll: &mut Vec<Box<dyn BufRead>>,
+    names: &Vec<String>,
+    split: bool,
+    rejects: &mut Rejects,
+    out: &mut impl Write,
+) {
+    // line numbers and end of the last range, for validation
+    let mut linenos = vec![0; ll.len()];
+    let mut ends: Vec<u128> = vec![0; ll.len()];
     // tick index list
     let mut il = vec![0; ll.len()];
     // temporary line list
     let mut tl: Vec<Option<Range>> = ll
         .iter_mut()
-        .map(|reader| {
-            let mut line = String::new();
-            reader.read_line(&mut line).unwrap();
-            parse_line(&line)
-        })
+        .enumerate()
+        .map(|(i, reader)| next_range(reader, &names[i], &mut linenos[i], &mut ends[i], rejects))
         .collect();
 
     // annotation list
@@ -122,7 +215,7 @@ fn merge_ticks(ll: &mut Vec<BufReader<File>>, split: bool) {
 
     // previous annotation
     let mut pg: Option<Vec<String>> = None;
-    let mut a: Option<u64> = None;
+    let mut a: Option<u128> = None;
 
     loop {
         let (n, i, j) = min_front(&tl, &il);
@@ -131,12 +224,14 @@ fn merge_ticks(ll: &mut Vec<BufReader<File>>, split: bool) {
         }
         if i == -1 {
             if !split && pg.is_some() {
-                println!(
+                writeln!(
+                    out,
                     "{},{},{}",
                     a.unwrap(),
                     pn.unwrap() - 1,
                     pg.unwrap().join(",")
-                );
+                )
+                .unwrap();
             }
             break;
         }
@@ -149,15 +244,17 @@ fn merge_ticks(ll: &mut Vec<BufReader<File>>, split: bool) {
                 if !fl.is_empty() {
                     let g: Vec<String> = fl.iter().map(|f| format!("{},{}", f.0, f.2)).collect();
                     if !split && pg.is_some() && pg.as_ref().unwrap() != &g {
-                        println!(
+                        writeln!(
+                            out,
                             "{},{},{}",
                             a.unwrap(),
                             pn.unwrap() - 1,
                             pg.as_ref().unwrap().join(",")
-                        ); // n-1 because .db file uses closed interval, i.e. [a,b]
+                        )
+                        .unwrap(); // n-1 because .db file uses closed interval, i.e. [a,b]
                         a = Some(pn_value);
                     } else if split {
-                        println!("{},{},{}", pn_value, n - 1, g.join(",")); // n-1 because .db file uses closed interval, i.e. [a,b]
+                        writeln!(out, "{},{},{}", pn_value, n - 1, g.join(",")).unwrap(); // n-1 because .db file uses closed interval, i.e. [a,b]
                     }
                     pg = Some(g);
                 }
@@ -173,27 +270,120 @@ fn merge_ticks(ll: &mut Vec<BufReader<File>>, split: bool) {
 
         il[i as usize] += 1;
         if il[i as usize] % 2 == 0 {
-            let mut line = String::new();
-            if ll[i as usize].read_line(&mut line).unwrap() > 0 {
-                tl[i as usize] = parse_line(&line);
-            } else {
-                tl[i as usize] = None;
-            }
+            let i = i as usize;
+            tl[i] = next_range(&mut ll[i], &names[i], &mut linenos[i], &mut ends[i], rejects);
         }
     }
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = get_option().unwrap();
+    let args = match get_option() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
 
-    let mut ll: Vec<BufReader<File>> = Vec::new();
+    let mut ll: Vec<Box<dyn BufRead>> = Vec::new();
+    let mut names: Vec<String> = Vec::new();
     for db_file in &args.inputs {
-        let file = File::open(db_file)?;
-        let reader = BufReader::new(file);
+        let reader = compress::open(&PathBuf::from(db_file))?;
         ll.push(reader);
+        names.push(db_file.to_string_lossy().to_string());
     }
 
-    merge_ticks(&mut ll, args.split);
+    if let Some(path) = &args.manifest {
+        // the file names only, the directories of a run mean nothing later
+        let dbs: Vec<String> = args
+            .inputs
+            .iter()
+            .map(|p| {
+                let p = PathBuf::from(p);
+                p.file_name().unwrap_or(p.as_os_str()).to_string_lossy().to_string()
+            })
+            .collect();
+        let mut w = compress::create_atomic(path, Codec::Plain)?;
+        write!(w, "{}", DbManifest::new(&dbs))?;
+        w.finish()?;
+    }
+
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine)?;
+    let mut out = compress::create(&PathBuf::from("-"), args.codec)?;
+    merge_ticks(&mut ll, &names, args.split, &mut rejects, &mut out);
+    out.finish()?;
+    rejects.summary()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    // 2001:db8::/32 and the rest of the IPv6 space past it
+    const DB8: u128 = 0x2001_0db8 << 96;
+    const DB8_END: u128 = DB8 + (1 << 96) - 1;
+
+    fn merge(dbs: &[&str], split: bool) -> (String, usize) {
+        let mut ll: Vec<Box<dyn BufRead>> = dbs
+            .iter()
+            .map(|db| Box::new(std::io::Cursor::new(db.to_string())) as Box<dyn BufRead>)
+            .collect();
+        let names = (0..dbs.len()).map(|i| format!("{}.db", i)).collect();
+        let mut rejects = Rejects::new(OnError::Skip, Path::new("unused")).unwrap();
+        let mut out = Vec::new();
+        merge_ticks(&mut ll, &names, split, &mut rejects, &mut out);
+        (String::from_utf8(out).unwrap(), rejects.count())
+    }
+
+    #[test]
+    fn parse_ipv6_ticks() {
+        let r = parse_line(&format!("\"{}\",\"{}\",\"CN\",\"x\"", DB8, DB8_END)).unwrap();
+        assert_eq!((r.a, r.b, r.g.as_str()), (DB8, DB8_END + 1, "CN"));
+        // the last address has no open end
+        let r = parse_line(&format!("0,{},US", u128::MAX)).unwrap();
+        assert_eq!(r.b, u128::MAX);
+        let r = parse_line("1,2,\"\"").unwrap();
+        assert_eq!(r.g, "-");
+    }
+
+    #[test]
+    fn malformed_lines() {
+        assert_eq!(parse_line("1,2").unwrap_err(), "expected at least 3 fields, got 2");
+        assert_eq!(parse_line("1,x,US").unwrap_err(), "not an address number: x");
+        assert_eq!(parse_line("-1,2,US").unwrap_err(), "not an address number: -1");
+        let past = format!("0,{}0,US", u128::MAX);
+        assert!(parse_line(&past).unwrap_err().starts_with("not an address number"));
+        assert_eq!(parse_line("5,4,US").unwrap_err(), "empty range 5-4");
+    }
+
+    #[test]
+    fn merge_ipv6() {
+        let a = format!("0,{},US\n{},{},CN\n", DB8 - 1, DB8, u128::MAX);
+        let b = format!("0,{},JP\n{},{},CN\n", DB8 + 40, DB8 + 41, u128::MAX);
+        let (out, rejected) = merge(&[&a, &b], false);
+        assert_eq!(rejected, 0);
+        let expected = format!(
+            "0,{},0,US,1,JP\n{},{},0,CN,1,JP\n{},{},0,CN,1,CN\n",
+            DB8 - 1,
+            DB8,
+            DB8 + 40,
+            DB8 + 41,
+            u128::MAX - 1
+        );
+        assert_eq!(out, expected);
+        // with -s, consecutive ranges of the same annotation stay apart
+        let (out, _) = merge(&[&a, &a], true);
+        assert_eq!(out.lines().count(), 2);
+    }
+
+    #[test]
+    fn overlapping_ranges_are_rejected() {
+        let a = format!("{},{},CN\n{},{},JP\n\n{},{},KR\n", DB8, DB8_END, DB8 + 1, DB8 + 2, DB8_END + 1, DB8_END + 9);
+        let (out, rejected) = merge(&[&a], false);
+        assert_eq!(rejected, 1);
+        assert_eq!(out, format!("{},{},0,CN\n{},{},0,KR\n", DB8, DB8_END, DB8_END + 1, DB8_END + 9));
+    }
+}
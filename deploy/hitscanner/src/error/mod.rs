@@ -0,0 +1,320 @@
+// Errors in the hitscanner inputs and what to do about them
+//   - Format: the input formats read by the binaries
+//   - InputError: a rejected record, with its file and line number
+//   - OnError: --on-error=skip|fail|quarantine
+//     - skip: drop the record and go on
+//     - fail: print the error and exit with 1 (default)
+//     - quarantine: like skip, and write the record to a side file
+//   - Rejects: applies the policy, and prints the rejected counts to STDERR;
+//     a record rejected with fail, or a quarantine file that can't be written,
+//     is an io::Error for the binary to exit with
+//   - read_line: the next line of a text input, a line that isn't UTF-8 or an
+//     unreadable input, e.g. a corrupt compressed stream, is a LineError
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Warts,     // binary warts object
+    WartsText, // sc_warts2text line
+    Link,      // link file line, see link::Link
+    GeoDb,     // .db geoIPDB line, see dbmerge
+    Pfx2As,    // routeviews prefix to AS line, see iputils::Pfx2As
+    Addr,      // a single IP address per line
+    Path,      // binary path record, see path::TracePath
+    Manifest,  // merged db manifest line, see iputils::DbManifest
+    Alias,     // alias file line, see alias::Aliases
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Format::Warts => "warts object",
+            Format::WartsText => "warts2text line",
+            Format::Link => "link line",
+            Format::GeoDb => "geo db line",
+            Format::Pfx2As => "pfx2as line",
+            Format::Addr => "address line",
+            Format::Path => "path record",
+            Format::Manifest => "manifest line",
+            Format::Alias => "alias line",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug)]
+pub struct InputError {
+    pub format: Format,
+    pub file: String,
+    pub line: usize, // 1-based, the object number for warts
+    pub msg: String,
+    pub raw: Option<String>, // the rejected line, if any
+}
+
+impl InputError {
+    pub fn new(format: Format, line: usize, msg: &str) -> Self {
+        InputError {
+            format,
+            file: String::from("-"),
+            line,
+            msg: msg.to_string(),
+            raw: None,
+        }
+    }
+
+    pub fn with_raw(mut self, raw: &str) -> Self {
+        self.raw = Some(raw.to_string());
+        self
+    }
+
+    pub fn in_file(mut self, file: &str) -> Self {
+        self.file = file.to_string();
+        self
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: invalid {}: {}", self.file, self.line, self.format, self.msg)
+    }
+}
+
+impl std::error::Error for InputError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    Skip,
+    Fail,
+    Quarantine,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(OnError::Skip),
+            "fail" => Ok(OnError::Fail),
+            "quarantine" => Ok(OnError::Quarantine),
+            _ => Err(format!("unknown --on-error mode {}, use skip, fail or quarantine", s)),
+        }
+    }
+}
+
+pub struct Rejects {
+    policy: OnError,
+    quarantine: Option<BufWriter<File>>,
+    counts: BTreeMap<String, usize>,
+}
+
+impl Rejects {
+    // `path` is the side file for OnError::Quarantine
+    pub fn new(policy: OnError, path: &Path) -> io::Result<Self> {
+        let quarantine = if policy == OnError::Quarantine {
+            let f = File::create(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("can't create {}: {}", path.display(), e)))?;
+            Some(BufWriter::new(f))
+        } else {
+            None
+        };
+        Ok(Rejects {
+            policy,
+            quarantine,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    // an error if the run must stop, with fail or if the record can't be quarantined
+    pub fn reject(&mut self, e: &InputError) -> io::Result<()> {
+        if self.policy == OnError::Fail {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+        }
+        *self.counts.entry(e.file.clone()).or_insert(0) += 1;
+        if let Some(q) = self.quarantine.as_mut() {
+            // the context as a comment, then the rejected line as is
+            writeln!(q, "# {}", e)?;
+            if let Some(raw) = &e.raw {
+                writeln!(q, "{}", raw)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    // print the rejected counts per file to STDERR, an error if the
+    // quarantine file can't be written out
+    pub fn summary(&mut self) -> io::Result<()> {
+        for (file, n) in &self.counts {
+            eprintln!("{}: {} rejected", file, n);
+        }
+        match self.quarantine.as_mut() {
+            Some(q) => q.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LineError {
+    Utf8(String), // the line, lossy, the input goes on
+    Io(io::Error),  // the input can't be read any further
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineError::Utf8(_) => write!(f, "not UTF-8"),
+            LineError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl LineError {
+    // the rejected record of the error, at line `line` of `file`
+    pub fn reject(&self, format: Format, line: usize, file: &str) -> InputError {
+        let e = InputError::new(format, line, &self.to_string()).in_file(file);
+        match self {
+            LineError::Utf8(raw) => e.with_raw(raw),
+            LineError::Io(_) => e,
+        }
+    }
+}
+
+// the next line without its line end, None at the end of input
+pub fn read_line(reader: &mut dyn BufRead) -> Option<Result<String, LineError>> {
+    let mut buf = Vec::new();
+    match reader.read_until(b'\n', &mut buf) {
+        Ok(0) => None,
+        Ok(_) => {
+            while buf.last().is_some_and(|c| *c == b'\n' || *c == b'\r') {
+                buf.pop();
+            }
+            Some(String::from_utf8(buf).map_err(|e| LineError::Utf8(String::from_utf8_lossy(e.as_bytes()).to_string())))
+        }
+        Err(e) => Some(Err(LineError::Io(e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor, Read};
+
+    // an input that fails after its first bytes, like a truncated compressed stream
+    struct Broken(Cursor<Vec<u8>>);
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt stream")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn read_line_strips_line_ends() {
+        let mut r = Cursor::new(b"a b\r\nc\n\nlast".to_vec());
+        let lines: Vec<String> = std::iter::from_fn(|| read_line(&mut r)).map(|l| l.unwrap()).collect();
+        assert_eq!(lines, ["a b", "c", "", "last"]);
+    }
+
+    #[test]
+    fn read_line_goes_on_after_bad_utf8() {
+        let mut r = Cursor::new(b"ok\nbad \xff\xfe\nnext\n".to_vec());
+        assert_eq!(read_line(&mut r).unwrap().unwrap(), "ok");
+        let e = read_line(&mut r).unwrap().unwrap_err();
+        assert!(matches!(&e, LineError::Utf8(raw) if raw == "bad \u{fffd}\u{fffd}"), "{:?}", e);
+        let rejected = e.reject(Format::Link, 2, "x.links");
+        assert_eq!(rejected.to_string(), "x.links:2: invalid link line: not UTF-8");
+        assert_eq!(rejected.raw.as_deref(), Some("bad \u{fffd}\u{fffd}"));
+        assert_eq!(read_line(&mut r).unwrap().unwrap(), "next");
+        assert!(read_line(&mut r).is_none());
+    }
+
+    #[test]
+    fn read_line_stops_at_unreadable_input() {
+        let mut r = BufReader::new(Broken(Cursor::new(b"first\n".to_vec())));
+        assert_eq!(read_line(&mut r).unwrap().unwrap(), "first");
+        let e = read_line(&mut r).unwrap().unwrap_err();
+        assert!(matches!(e, LineError::Io(_)), "{:?}", e);
+        let rejected = e.reject(Format::GeoDb, 2, "a.db");
+        assert_eq!(rejected.to_string(), "a.db:2: invalid geo db line: corrupt stream");
+        assert!(rejected.raw.is_none());
+    }
+
+    #[test]
+    fn on_error_modes() {
+        assert_eq!("skip".parse::<OnError>(), Ok(OnError::Skip));
+        assert_eq!("fail".parse::<OnError>(), Ok(OnError::Fail));
+        assert_eq!("quarantine".parse::<OnError>(), Ok(OnError::Quarantine));
+        assert!("ignore".parse::<OnError>().is_err());
+    }
+
+    #[test]
+    fn skip_counts_per_file() {
+        let mut rejects = Rejects::new(OnError::Skip, Path::new("unused")).unwrap();
+        for (file, line) in [("a", 1), ("b", 4), ("a", 7)] {
+            let e = InputError::new(Format::Addr, line, "not an IPv4 address").in_file(file);
+            rejects.reject(&e).unwrap();
+        }
+        assert_eq!(rejects.count(), 3);
+        assert_eq!(rejects.counts.get("a"), Some(&2));
+        assert!(!Path::new("unused").exists());
+    }
+
+    #[test]
+    fn quarantine_keeps_the_rejected_lines() {
+        let path = std::env::temp_dir().join(format!("hitscanner-quarantine-{}", std::process::id()));
+        let mut rejects = Rejects::new(OnError::Quarantine, &path).unwrap();
+        rejects
+            .reject(
+            &InputError::new(Format::Link, 3, "expected 10 to 12 fields, got 2")
+                .in_file("x.links")
+                .with_raw("1.2.3.4 5.6.7.8"),
+            )
+            .unwrap();
+        rejects
+            .reject(&InputError::new(Format::Warts, 9, "truncated object").in_file("x.warts"))
+            .unwrap();
+        rejects.summary().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            text,
+            "# x.links:3: invalid link line: expected 10 to 12 fields, got 2\n\
+             1.2.3.4 5.6.7.8\n\
+             # x.warts:9: invalid warts object: truncated object\n"
+        );
+        assert_eq!(rejects.count(), 2);
+    }
+
+    #[test]
+    fn fail_is_an_error() {
+        let mut rejects = Rejects::new(OnError::Fail, Path::new("unused")).unwrap();
+        let e = rejects.reject(&InputError::new(Format::Pfx2As, 2, "not a prefix").in_file("rv"));
+        assert_eq!(e.unwrap_err().to_string(), "rv:2: invalid pfx2as line: not a prefix");
+    }
+
+    #[test]
+    fn unwritable_quarantine_is_an_error() {
+        let dir = std::env::temp_dir().join(format!("hitscanner-no-such-dir-{}", std::process::id()));
+        let e = Rejects::new(OnError::Quarantine, &dir.join("q")).err().unwrap();
+        assert!(e.to_string().starts_with("can't create "), "{}", e);
+        // a full disk shows when the quarantine file is written out
+        if Path::new("/dev/full").exists() {
+            let mut rejects = Rejects::new(OnError::Quarantine, Path::new("/dev/full")).unwrap();
+            rejects.reject(&InputError::new(Format::Addr, 1, "not an IPv4 address")).unwrap();
+            assert!(rejects.summary().is_err());
+        }
+    }
+}
@@ -1,31 +1,37 @@
-mod iputils;
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{read_line, Format, InputError, LineError, OnError, Rejects};
+use hitscanner::iputils::{host_prefix, IPLabeller, IPRange};
+use trie::common::Prefix;
 
-use iputils::IPLabeller;
-use trie::common::{NoMeta, Prefix};
-
-use std::{
-    io::{stdin, BufRead},
-    path::PathBuf,
-};
-use std::net::Ipv4Addr;
-
-use crate::iputils::IPRange;
+use std::{io::Write, path::PathBuf};
+use std::net::IpAddr;
 
 const HELP: &str = "\
 Usage: iplabel
 
 OPTIONS:
-    -g         merged.db / merged.csv file
+    -g         merged.db / merged.csv file of IPv4 ranges
+    -G         merged.db / merged.csv file of IPv6 ranges
+    --on-error    skip|fail|quarantine malformed or unmatched addresses, and those
+                  of a family without a -g or -G db, default fail
+    --quarantine  file of the quarantined lines, default iplabel.rejected
+    --compress    none|gzip|zstd|xz|bz2 compression of the output, default none
 INPUT:
-    stdin each line is an IPv4Addr
+    stdin each line is an IPv4Addr or IPv6Addr
+    the input and the db files may be gzip/zstd/xz/bz2 compressed
 OUTPUT:
-    labelled IPv4Addr. e.g.
+    labelled IP address. e.g.
     114.114.114.114 0,CN,1,CN,2,CN,3,CN,4,CN,5,CN
+    2400:3200::1 0,CN,1,CN,2,CN
 ";
 
 #[allow(dead_code)]
 struct AppArgs {
-    geo: PathBuf,
+    geo: Option<PathBuf>,
+    geo6: Option<PathBuf>,
+    on_error: OnError,
+    quarantine: PathBuf,
+    codec: Codec,
 }
 
 fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
@@ -42,13 +48,40 @@ fn getoption() -> Result<AppArgs, pico_args::Error> {
     }
 
     let args = AppArgs {
-        geo: pargs.value_from_os_str(["-g", "--geo"], parse_path)?,
+        geo: pargs.opt_value_from_os_str(["-g", "--geo"], parse_path)?,
+        geo6: pargs.opt_value_from_os_str(["-G", "--geo6"], parse_path)?,
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("iplabel.rejected")),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
     };
 
+    if args.geo.is_none() && args.geo6.is_none() {
+        print!("{}", HELP);
+        std::process::exit(0);
+    }
+
     Ok(args)
 }
 
-type Prefix4NoMeta<'a> = Prefix<u32, NoMeta>;
+fn labeller_or_exit<T>(r: Result<T, InputError>) -> T {
+    match r {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// a rejected line, the run stops with --on-error fail or if it can't be quarantined
+fn reject_or_exit(rejects: &mut Rejects, e: &InputError) {
+    if let Err(e) = rejects.reject(e) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+}
 
 fn main() {
     let args = match getoption() {
@@ -60,14 +93,72 @@ fn main() {
     };
 
     let mut pfxs: Vec<Prefix<u32, String>> = vec![];
-    let geo_labeller: IPLabeller<IPRange> = IPLabeller::new(&args.geo, &mut pfxs);
+    let geo_labeller: Option<IPLabeller<IPRange>> = args
+        .geo
+        .as_ref()
+        .map(|g| labeller_or_exit(IPLabeller::new(g, &mut pfxs)));
+    let mut pfxs6: Vec<Prefix<u128, String>> = vec![];
+    let geo6_labeller: Option<IPLabeller<IPRange<u128>>> = args
+        .geo6
+        .as_ref()
+        .map(|g| labeller_or_exit(IPLabeller::new(g, &mut pfxs6)));
 
-    for l in stdin().lock().lines() {
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine).unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    let stdio = PathBuf::from("-");
+    let mut input = compress::open(&stdio).unwrap_or_else(|e| {
+        eprintln!("Error: -: {}.", e);
+        std::process::exit(1);
+    });
+    let write_err = |e: std::io::Error| -> ! {
+        eprintln!("Error: can't write -: {}.", e);
+        std::process::exit(1);
+    };
+    let mut out = compress::create(&stdio, args.codec).unwrap_or_else(|e| write_err(e));
+    let mut lineno = 0;
+    while let Some(l) = read_line(&mut input) {
+        lineno += 1;
+        let l = match l {
+            Ok(l) => l,
+            Err(e) => {
+                reject_or_exit(&mut rejects, &e.reject(Format::Addr, lineno, "-"));
+                match e {
+                    LineError::Utf8(_) => continue,
+                    LineError::Io(_) => break,
+                }
+            }
+        };
+        let reject = |msg: &str| InputError::new(Format::Addr, lineno, msg).with_raw(&l);
         // destination IP address
-        let ip: Ipv4Addr = l.as_ref().unwrap().parse().unwrap();
-        let p = Prefix4NoMeta::new(ip.into(), 32);
-        let r = geo_labeller.match_pfx(&p);
-        let db_geos = r.unwrap().meta.as_ref().unwrap();
-        println!("{} {}", ip, db_geos);
+        let ip: IpAddr = match l.trim().parse() {
+            Ok(ip) => ip,
+            Err(_) => {
+                reject_or_exit(&mut rejects, &reject("not an IP address"));
+                continue;
+            }
+        };
+        let r = match (ip, &geo_labeller, &geo6_labeller) {
+            (IpAddr::V4(a), Some(g), _) => g.match_pfx(&host_prefix(a)).and_then(|r| r.meta.as_ref()),
+            (IpAddr::V6(a), _, Some(g)) => g.match_pfx(&host_prefix(a)).and_then(|r| r.meta.as_ref()),
+            (IpAddr::V4(_), None, _) => {
+                reject_or_exit(&mut rejects, &reject("no IPv4 database given, see -g"));
+                continue;
+            }
+            (IpAddr::V6(_), _, None) => {
+                reject_or_exit(&mut rejects, &reject("no IPv6 database given, see -G"));
+                continue;
+            }
+        };
+        match r {
+            Some(db_geos) => writeln!(out, "{} {}", ip, db_geos).unwrap_or_else(|e| write_err(e)),
+            None => reject_or_exit(&mut rejects, &reject("no matching range")),
+        }
+    }
+    out.finish().unwrap_or_else(|e| write_err(e));
+    if let Err(e) = rejects.summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
     }
 }
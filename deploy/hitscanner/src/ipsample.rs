@@ -1,12 +1,12 @@
-mod iputils;
-
-use iputils::parse_prefix_str;
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{Format, InputError};
+use hitscanner::iputils::{host_mask, parse_prefix_str, Family};
 use trie::common::{NoMeta, Prefix};
 
 use std::{
     cmp::{max, min},
     fs,
-    io::{stdin, BufRead},
+    io::{BufRead, Write},
     path::PathBuf,
 };
 
@@ -19,8 +19,11 @@ Usage: ipsample [OPTIONS]
 OPTIONS:
     -c         path to task config
     -t         type: UNIFORM, RANDOM_UNIFORM
-    -d         density
+    -d         density, the prefix length to sample at, e.g. 24 for IPv4, 48 for IPv6
     -o         offset
+    --compress none|gzip|zstd|xz|bz2 compression of the output, default none
+INPUT:
+    stdin each line is an IPv4 or IPv6 prefix, plain or gzip/zstd/xz/bz2 compressed
 ";
 
 struct AppArgs {
@@ -28,6 +31,7 @@ struct AppArgs {
     r#type: Option<String>,
     density: Option<u8>,
     offset: Option<u32>,
+    codec: Codec,
 }
 
 fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
@@ -48,6 +52,7 @@ fn get_option() -> Result<AppArgs, pico_args::Error> {
         density: pargs.opt_value_from_str(["-d", "--density"])?,
         r#type: pargs.opt_value_from_str(["-t", "--type"])?,
         offset: pargs.opt_value_from_str(["-o", "--offset"])?,
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
     };
 
     if args.config.is_none()
@@ -62,30 +67,38 @@ fn get_option() -> Result<AppArgs, pico_args::Error> {
     Ok(args)
 }
 
-fn random_uniform_sample(p: Prefix<u32, NoMeta>, density: u8) {
-    let a = p.net; // start address
-    let g: u32 = 1u32 << (32 - max(density, p.len)); // granularity, (density and p.len both > 0)
-    let n = 1u32 << 32 - &p.len;
+fn random_uniform_sample<AF: Family>(p: Prefix<AF, NoMeta>, density: u8, out: &mut impl Write) {
+    let a = p.net.to_u128(); // start address
+    let gbits = AF::MAX_LEN - max(density, p.len); // granularity is 2^gbits, (density and p.len both > 0)
+    let n = host_mask(AF::MAX_LEN - p.len); // prefix size - 1
     let mut rng = thread_rng();
-    let mut o = rng.gen_range(0..g); // offset is randomly chosen
-    o = max(0, min(o, n - 1)); // clamp offset between [0, n-1] to make sure it's inside the prefix range
+    let mut o = rng.gen_range(0..=host_mask(gbits)); // offset is randomly chosen
+    o = min(o, n); // clamp offset between [0, n-1] to make sure it's inside the prefix range
 
-    for i in (0..n).step_by(g as usize) {
-        println!("{}", std::net::Ipv4Addr::from(a + (i + o) % n));
+    for i in 0..1u128 << (max(density, p.len) - p.len) {
+        writeln!(out, "{}", AF::from_u128(a + (((i << gbits) + o) & n)).to_addr()).unwrap();
     }
 }
 
-fn uniform_sample(p: Prefix<u32, NoMeta>, density: u8, offset: u32) {
-    let a = p.net; // start address
-    let g: u32 = 1u32 << (32 - max(density, p.len)); // granularity, (density and p.len both > 0)
-    let n = 1u32 << 32 - &p.len;
-    let o = max(0, min(offset, n - 1)); // clamp offset between [0, n-1] to make sure it's inside the prefix range
+fn uniform_sample<AF: Family>(p: Prefix<AF, NoMeta>, density: u8, offset: u32, out: &mut impl Write) {
+    let a = p.net.to_u128(); // start address
+    let gbits = AF::MAX_LEN - max(density, p.len); // granularity is 2^gbits, (density and p.len both > 0)
+    let n = host_mask(AF::MAX_LEN - p.len); // prefix size - 1
+    let o = min(offset as u128, n); // clamp offset between [0, n-1] to make sure it's inside the prefix range
 
-    for i in (0..n).step_by(g as usize) {
-        println!("{}", std::net::Ipv4Addr::from(a + (i + o) % n));
+    for i in 0..1u128 << (max(density, p.len) - p.len) {
+        writeln!(out, "{}", AF::from_u128(a + (((i << gbits) + o) & n)).to_addr()).unwrap();
     }
 }
 
+fn sample<AF: Family>(p: Prefix<AF, NoMeta>, r#type: &str, density: u8, offset: u32, out: &mut impl Write) {
+    match r#type {
+        "UNIFORM" => uniform_sample(p, density, offset, out),
+        "RANDOM_UNIFORM" => random_uniform_sample(p, density, out),
+        _ => {}
+    };
+}
+
 fn main() {
     let args = match get_option() {
         Ok(v) => v,
@@ -120,13 +133,20 @@ fn main() {
         offset = args.offset.expect("No sampling offset given");
     }
 
-    for l in stdin().lock().lines() {
-        // destination IP address
-        let p = parse_prefix_str(l.as_ref().unwrap());
-        match r#type.as_str() {
-            "UNIFORM" => uniform_sample(p, density, offset),
-            "RANDOM_UNIFORM" => random_uniform_sample(p, density),
-            _ => {}
+    let input = compress::open(&PathBuf::from("-")).unwrap();
+    let mut out = compress::create(&PathBuf::from("-"), args.codec).unwrap();
+    for (i, l) in input.lines().enumerate() {
+        // destination prefix, IPv4 or IPv6
+        let l = l.unwrap();
+        let r = if l.contains(':') {
+            parse_prefix_str::<u128>(&l).map(|p| sample(p, &r#type, density, offset, &mut out))
+        } else {
+            parse_prefix_str::<u32>(&l).map(|p| sample(p, &r#type, density, offset, &mut out))
         };
+        if let Err(e) = r {
+            eprintln!("Error: {}.", InputError::new(Format::Addr, i + 1, &e));
+            std::process::exit(1);
+        }
     }
+    out.finish().unwrap();
 }
@@ -1,19 +1,82 @@
 // Utilities for geopt, based on https://github.com/NLnetLabs/try-tries-and-trees
+//   - Family: IPv4 (u32) or IPv6 (u128) address family
 //   - IPRange: useful for parsing geodb raw data
 //     - implements from into trait for Vec<Prefix>
 //   - PrefixGeo: prefix meta data that holds
 //     - 2 letters country code (u16)
+//   - area_codes: the country codes of a list of codes and region names
+//   - Pfx2As: routeviews prefix to AS lines, labelled with the origin ASNs
+//   - DbManifest: the source databases of a merged db, by index, see dbmerge -m
 
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::{
+    collections::BTreeSet,
     convert::{From, TryFrom},
-    fs::File,
-    io::{BufRead, BufReader},
+    fmt,
+    io::BufRead,
     marker::PhantomData,
-    net::Ipv4Addr,
-    path::PathBuf,
+    net::{Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
 };
-use trie::common::{NoMeta, Prefix, Trie};
+use trie::common::{AddressFamily, NoMeta, Prefix, Trie};
+
+use crate::compress;
+use crate::error::{Format, InputError};
+
+// Address family of a trie: u32 for IPv4, u128 for IPv6
+// arithmetic is done on u128 so the helpers below serve both
+pub trait Family: AddressFamily {
+    type Addr: FromStr + Display + Copy;
+    const MAX_LEN: u8;
+    fn from_u128(v: u128) -> Self;
+    fn to_u128(self) -> u128;
+    fn from_addr(a: Self::Addr) -> Self;
+    fn to_addr(self) -> Self::Addr;
+}
+
+impl Family for u32 {
+    type Addr = Ipv4Addr;
+    const MAX_LEN: u8 = 32;
+    fn from_u128(v: u128) -> Self {
+        v as u32
+    }
+    fn to_u128(self) -> u128 {
+        self as u128
+    }
+    fn from_addr(a: Ipv4Addr) -> Self {
+        a.into()
+    }
+    fn to_addr(self) -> Ipv4Addr {
+        self.into()
+    }
+}
+
+impl Family for u128 {
+    type Addr = Ipv6Addr;
+    const MAX_LEN: u8 = 128;
+    fn from_u128(v: u128) -> Self {
+        v
+    }
+    fn to_u128(self) -> u128 {
+        self
+    }
+    fn from_addr(a: Ipv6Addr) -> Self {
+        a.into()
+    }
+    fn to_addr(self) -> Ipv6Addr {
+        self.into()
+    }
+}
+
+// the lowest `bits` bits set, i.e. the host part of a prefix
+pub fn host_mask(bits: u8) -> u128 {
+    if bits >= 128 {
+        u128::MAX
+    } else {
+        (1u128 << bits) - 1
+    }
+}
 
 // ISO 3166-1 alpha-2: country code
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -43,32 +106,80 @@ impl TryFrom<&str> for CountryCodeAlpha2 {
     }
 }
 
+// named regions of area_codes, by their members' ISO 3166-1 alpha-2 codes
+const REGIONS: &[(&str, &[&str])] = &[
+    (
+        "EU",
+        &[
+            "AT", "BE", "BG", "HR", "CY", "CZ", "DK", "EE", "FI", "FR", "DE", "GR", "HU", "IE", "IT", "LV",
+            "LT", "LU", "MT", "NL", "PL", "PT", "RO", "SK", "SI", "ES", "SE",
+        ],
+    ),
+    (
+        "EEA",
+        &[
+            "AT", "BE", "BG", "HR", "CY", "CZ", "DK", "EE", "FI", "FR", "DE", "GR", "HU", "IE", "IT", "LV",
+            "LT", "LU", "MT", "NL", "PL", "PT", "RO", "SK", "SI", "ES", "SE", "IS", "LI", "NO",
+        ],
+    ),
+    ("ASEAN", &["BN", "KH", "ID", "LA", "MY", "MM", "PH", "SG", "TH", "VN"]),
+    ("GCC", &["AE", "BH", "KW", "OM", "QA", "SA"]),
+];
+
+// the country codes of a comma separated list of codes and region names,
+// e.g. CN,HK or EU,CH, case insensitive; a region name wins over a code,
+// so EU is the member states, not the EU pseudo-code of some databases
+pub fn area_codes(area: &str) -> Result<BTreeSet<String>, String> {
+    let mut codes = BTreeSet::new();
+    for a in area.split(',') {
+        let a = a.trim().to_uppercase();
+        if let Some((_, members)) = REGIONS.iter().find(|(name, _)| *name == a) {
+            codes.extend(members.iter().map(|c| c.to_string()));
+        } else if a.len() == 2 && a.chars().all(|c| c.is_ascii_uppercase()) {
+            codes.insert(a);
+        } else {
+            let names: Vec<&str> = REGIONS.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "not a country code or region: {}, regions are {}",
+                a,
+                names.join(", ")
+            ));
+        }
+    }
+    Ok(codes)
+}
+
 // can't patch Prefix since it's in another crate
-pub fn parse_prefix_str(ps: &str) -> Prefix<u32, NoMeta> {
-    let mut p = ps.split("/");
-    let ip: Vec<_> = p
-        .next()
-        .unwrap()
-        .split(".")
-        .map(|o| -> u8 { o.parse().unwrap() })
-        .collect();
-    let net: u32 = Ipv4Addr::new(ip[0], ip[1], ip[2], ip[3]).into();
-    let len: u8 = p.next().unwrap().parse().unwrap();
+pub fn parse_prefix_str<AF: Family>(ps: &str) -> Result<Prefix<AF, NoMeta>, String> {
+    let err = || format!("invalid prefix: {}", ps);
+    let mut p = ps.trim().split("/");
+    let ip: AF::Addr = p.next().unwrap().parse().map_err(|_| err())?;
+    let net = AF::from_addr(ip).to_u128();
+    let len: u8 = match p.next().map(|l| l.parse()) {
+        Some(Ok(len)) if len <= AF::MAX_LEN => len,
+        _ => return Err(err()),
+    };
     // make sure the last $len bits are zeros
-    Prefix::<u32, NoMeta>::new(net >> (32-len) << (32-len), len)
+    let net = net & !host_mask(AF::MAX_LEN - len);
+    Ok(Prefix::<AF, NoMeta>::new(AF::from_u128(net), len))
+}
+
+// a single address as a host prefix, e.g. /32 for IPv4
+pub fn host_prefix<AF: Family>(ip: AF::Addr) -> Prefix<AF, NoMeta> {
+    Prefix::<AF, NoMeta>::new(AF::from_addr(ip), AF::MAX_LEN)
 }
 
 // Inclusive range [a, b]
-pub struct IPRange {
-    pub a: u32,
-    pub b: u32,
+pub struct IPRange<AF = u32> {
+    pub a: AF,
+    pub b: AF,
 }
 
-fn r2c_helper(a: u64, b: u64, l: u64, h: u64) -> Vec<(u64, u64)> {
+fn r2c_helper(a: u128, b: u128, l: u128, h: u128) -> Vec<(u128, u128)> {
     if (a, b) == (l, h) {
         return vec![(l, h)];
     }
-    let m = (h + l) / 2;
+    let m = l + (h - l) / 2;
     if b <= m {
         r2c_helper(a, b, l, m)
     } else if a >= m + 1 {
@@ -81,13 +192,15 @@ fn r2c_helper(a: u64, b: u64, l: u64, h: u64) -> Vec<(u64, u64)> {
     }
 }
 
-fn iprange2_prefix(a: u64, b: u64) -> Vec<Prefix<u32, NoMeta>> {
-    let (l, h) = (0, 0xFF_FF_FF_FFu64);
+fn iprange2_prefix<AF: Family>(a: u128, b: u128) -> Vec<Prefix<AF, NoMeta>> {
+    let (l, h) = (0, host_mask(AF::MAX_LEN));
     r2c_helper(a, b, l, h)
         .into_iter()
         .map(|(start, end)| {
-            let prefix_length = 32 - ((end - start + 1) as f64).log2() as u8;
-            Prefix::<u32, NoMeta>::new(start as u32, prefix_length)
+            // end - start + 1 is a power of 2, and may not fit in u128
+            let host_len = (128 - (end - start).leading_zeros()) as u8;
+            let prefix_length = AF::MAX_LEN - host_len;
+            Prefix::<AF, NoMeta>::new(AF::from_u128(start), prefix_length)
         })
         .collect()
 }
@@ -111,75 +224,237 @@ def r2c_helper(a, b, l, h):
 
 # 必然能通过二分找到对齐区间两个端点a,b的前缀。
 # l, h表示包含a, b的，且为2的整数倍的，上下界。
-# 初始为0~2^32-1，通过二分逐步缩小。
+# 初始为0~2^32-1（IPv6为0~2^128-1），通过二分逐步缩小。
 def range2cidr(a, b):
   l, h = 0, 2**32-1
   return map( lambda x: int2ip(x[0])+'/'+str(32-int(math.log(x[1]-x[0]+1, 2))), r2c_helper(a, b, l, h)
 */
-impl Into<Vec<Prefix<u32, NoMeta>>> for IPRange {
-    fn into(self) -> Vec<Prefix<u32, NoMeta>> {
-        iprange2_prefix(self.a as u64, self.b as u64)
+impl<AF: Family> Into<Vec<Prefix<AF, NoMeta>>> for IPRange<AF> {
+    fn into(self) -> Vec<Prefix<AF, NoMeta>> {
+        iprange2_prefix(self.a.to_u128(), self.b.to_u128())
     }
 }
 
 // IP Labeller
 #[allow(dead_code)]
 pub struct IPLabeller<'a, T: ProcessLine>(
-    pub Trie<'a, u32, String>,
-    &'a Vec<Prefix<u32, String>>,
+    pub Trie<'a, T::AF, String>,
+    &'a Vec<Prefix<T::AF, String>>,
     PhantomData<T>,
 );
 
 pub trait ProcessLine {
-    fn process_line(line: &String) -> Vec<Prefix<u32, String>>;
+    type AF: Family;
+    const FORMAT: Format = Format::GeoDb; // of the errors
+    fn process_line(line: &String) -> Result<Vec<Prefix<Self::AF, String>>, String>;
 }
 
-impl ProcessLine for IPRange {
-    fn process_line(line: &String) -> Vec<Prefix<u32, String>> {
+impl<AF: Family> ProcessLine for IPRange<AF> {
+    type AF = AF;
+    fn process_line(line: &String) -> Result<Vec<Prefix<AF, String>>, String> {
         let mut fields = line.split(',');
-        let r = IPRange {
-            a: fields.next().unwrap().parse().unwrap(),
-            b: fields.next().unwrap().parse().unwrap(),
+        let mut num = || -> Result<AF, String> {
+            let f = fields.next().ok_or("missing range field")?;
+            match f.parse::<u128>() {
+                Ok(v) if v <= host_mask(AF::MAX_LEN) => Ok(AF::from_u128(v)),
+                _ => Err(format!("not an address number: {}", f)),
+            }
         };
+        let r = IPRange::<AF> { a: num()?, b: num()? };
+        if r.a.to_u128() > r.b.to_u128() {
+            return Err(String::from("empty range"));
+        }
         let g: String = fields.collect::<Vec<_>>().join(",");
-        let pv: Vec<Prefix<u32, NoMeta>> = r.into();
+        let pv: Vec<Prefix<AF, NoMeta>> = r.into();
         let mut pfxs: Vec<_> = vec![];
         for p in pv {
             pfxs.push(Prefix::new_with_meta(p.net, p.len, g.clone()));
         }
-        pfxs
+        Ok(pfxs)
     }
 }
 
-impl ProcessLine for Prefix<u32, String> {
-    fn process_line(line: &String) -> Vec<Prefix<u32, String>> {
+impl<AF: Family> ProcessLine for Prefix<AF, String> {
+    type AF = AF;
+    fn process_line(line: &String) -> Result<Vec<Prefix<AF, String>>, String> {
         let mut fields = line.split(' ');
         let r1 = fields.next().unwrap();
-        let pfx = parse_prefix_str(&r1);
-        let g = fields.next().unwrap().to_string();
-        vec![Prefix::new_with_meta(pfx.net, pfx.len, g)]
+        let pfx = parse_prefix_str::<AF>(&r1)?;
+        let g = fields.next().ok_or("missing label field")?.to_string();
+        Ok(vec![Prefix::new_with_meta(pfx.net, pfx.len, g)])
+    }
+}
+
+// A routeviews pfx2as line, e.g. of CAIDA's routeviews-rv2-20230224-1200.pfx2as
+//     1.0.0.0	24	13335
+// the prefix, its length and its origins, tab or comma separated, 4_5 for a
+// multi-origin prefix and 4,5 for an AS set, both labelled 4_5
+pub struct Pfx2As<AF = u32>(PhantomData<AF>);
+
+impl<AF: Family> ProcessLine for Pfx2As<AF> {
+    type AF = AF;
+    const FORMAT: Format = Format::Pfx2As;
+    fn process_line(line: &String) -> Result<Vec<Prefix<AF, String>>, String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(vec![]);
+        }
+        let sep = if line.contains('\t') { '\t' } else { ',' };
+        let mut fields = line.splitn(3, sep);
+        let net = fields.next().unwrap();
+        let len = fields.next().ok_or("missing prefix length field")?;
+        let origins = fields.next().ok_or("missing origin field")?;
+        let pfx = parse_prefix_str::<AF>(&format!("{}/{}", net.trim(), len.trim()))?;
+        let mut asns = Vec::new();
+        for asn in origins.trim().split(['_', ',']) {
+            asns.push(asn.parse::<u32>().map_err(|_| format!("not an ASN: {}", asn))?);
+        }
+        let asns: Vec<String> = asns.iter().map(|a| a.to_string()).collect();
+        Ok(vec![Prefix::new_with_meta(pfx.net, pfx.len, asns.join("_"))])
     }
 }
 
 #[allow(dead_code)]
 impl<'a, T: ProcessLine> IPLabeller<'a, T> {
-    pub fn new(path: &PathBuf, pfxs: &'a mut Vec<Prefix<u32, String>>) -> Self {
-        let mut trie = Trie::<u32, String>::new();
-        for line in BufReader::new(File::open(&path).unwrap()).lines() {
-            for p in <T as ProcessLine>::process_line(line.as_ref().unwrap()) {
+    pub fn new(path: &Path, pfxs: &'a mut Vec<Prefix<T::AF, String>>) -> Result<Self, InputError> {
+        let name = path.display().to_string();
+        let file = compress::open(path)
+            .map_err(|e| InputError::new(T::FORMAT, 0, &e.to_string()).in_file(&name))?;
+        let mut trie = Trie::<T::AF, String>::new();
+        for (i, line) in file.lines().enumerate() {
+            let err = |e: &str| InputError::new(T::FORMAT, i + 1, e).in_file(&name);
+            let line = line.map_err(|e| err(&e.to_string()))?;
+            for p in <T as ProcessLine>::process_line(&line).map_err(|e| err(&e).with_raw(&line))? {
                 pfxs.push(p);
             }
         }
         for pfx in pfxs.iter() {
             trie.insert(pfx);
         }
-        IPLabeller(trie, pfxs, PhantomData)
+        Ok(IPLabeller(trie, pfxs, PhantomData))
     }
 
     pub fn match_pfx(
         &self,
-        pfx: &Prefix<u32, NoMeta>,
-    ) -> Option<&trie::common::Prefix<u32, String>> {
+        pfx: &Prefix<T::AF, NoMeta>,
+    ) -> Option<&trie::common::Prefix<T::AF, String>> {
         self.0.match_longest_prefix(pfx)
     }
+
+    // every prefix of the db, with its label
+    pub fn prefixes(&self) -> &[Prefix<T::AF, String>] {
+        self.1
+    }
+}
+
+// The source databases of a merged db, in the order of the indexes in its
+// lines, e.g. 0,"AU",1,"AU" has two. dbmerge -m writes it as a sidecar
+// manifest, one index,name line per source, e.g.
+//     0,ip2location.db
+//     1,maxmind.db
+// names are kept free of ,;= and spaces so they fit in header lines
+#[derive(Clone, Debug, PartialEq)]
+pub struct DbManifest {
+    pub names: Vec<String>,
+}
+
+fn manifest_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_whitespace() || c.is_control() || ",;=".contains(c) { '_' } else { c })
+        .collect();
+    if name.is_empty() {
+        String::from("_")
+    } else {
+        name
+    }
+}
+
+impl DbManifest {
+    pub fn new(names: &[String]) -> Self {
+        DbManifest {
+            names: names.iter().map(|n| manifest_name(n)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // the indexes must be 0, 1, ... in order
+    pub fn read(path: &Path) -> Result<Self, InputError> {
+        let name = path.display().to_string();
+        let file = compress::open(path)
+            .map_err(|e| InputError::new(Format::Manifest, 0, &e.to_string()).in_file(&name))?;
+        let mut names = Vec::new();
+        for (i, line) in file.lines().enumerate() {
+            let err = |e: &str| InputError::new(Format::Manifest, i + 1, e).in_file(&name);
+            let line = line.map_err(|e| err(&e.to_string()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (index, db) = line.split_once(',').ok_or_else(|| err("expected index,name").with_raw(&line))?;
+            if index.trim().parse::<usize>() != Ok(names.len()) {
+                return Err(err(&format!("expected index {}", names.len())).with_raw(&line));
+            }
+            names.push(manifest_name(db.trim()));
+        }
+        if names.is_empty() {
+            return Err(InputError::new(Format::Manifest, 0, "no databases").in_file(&name));
+        }
+        Ok(DbManifest { names })
+    }
+
+    // without a manifest: one database per index seen in the merged db,
+    // named db0, db1, ...
+    pub fn infer<AF: Family>(pfxs: &[Prefix<AF, String>]) -> Result<Self, String> {
+        let mut n = 0;
+        for p in pfxs {
+            for index in db_indexes(p.meta.as_ref().unwrap())? {
+                n = n.max(index + 1);
+            }
+        }
+        Ok(DbManifest {
+            names: (0..n).map(|i| format!("db{}", i)).collect(),
+        })
+    }
+
+    // every index of the merged db must have a name
+    pub fn check<AF: Family>(&self, pfxs: &[Prefix<AF, String>]) -> Result<(), String> {
+        for p in pfxs {
+            let geos = p.meta.as_ref().unwrap();
+            if db_indexes(geos)?.iter().any(|&i| i >= self.len()) {
+                return Err(format!(
+                    "the merged db has more than the {} databases of the manifest: {}",
+                    self.len(),
+                    geos
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// the db indexes of a merged db annotation, e.g. 0,"AU",2,"CN" has 0 and 2
+fn db_indexes(geos: &str) -> Result<Vec<usize>, String> {
+    let f: Vec<&str> = geos.split(',').collect();
+    f.chunks(2)
+        .map(|c| match c {
+            [index, _] => index.parse::<usize>().map_err(|_| format!("not a db index: {}", index)),
+            _ => Err(format!("not a merged db annotation: {}", geos)),
+        })
+        .collect()
+}
+
+impl fmt::Display for DbManifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, name) in self.names.iter().enumerate() {
+            writeln!(f, "{},{}", i, name)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,28 @@
+// hitscanner -- shared library of the hitscanner binaries
+// =============================================================================
+//   - alias: the routers of an alias file, collapsed with union-find
+//   - compress: gzip, zstd, xz and bz2 input and output, detected by magic bytes
+//   - error: errors in the inputs and the --on-error policy
+//   - iputils: prefix parsing and IP labelling
+//   - warts: readers for warts and sc_warts2text traceroute data
+//   - trace: Trace, Hop and the TraceReader over any traceroute input
+//   - link: InOut, Link, LinkProp and the rules to merge them
+//   - linkio: link files in csv, jsonl, parquet and arrow formats
+//   - mpls: MPLS tunnels of the ICMP extensions and quoted TTL of a hop
+//   - path: the ordered hops of a trace in JSONL or a compact binary format
+//   - sketch: link delay estimators and the mergeable DelaySketch
+
+pub mod alias;
+pub mod compress;
+pub mod error;
+pub mod iputils;
+pub mod link;
+pub mod linkio;
+pub mod mpls;
+pub mod path;
+pub mod sketch;
+pub mod trace;
+pub mod warts;
+
+pub use link::{InOut, Link, LinkProp};
+pub use trace::{Hop, Trace, TraceReader};
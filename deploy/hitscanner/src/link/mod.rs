@@ -0,0 +1,723 @@
+// IP-level links, as written by trace2link and read by linkmerge
+//   - InOut: the ingress and outgress interface of a link
+//   - LinkProp: properties of a link, merged over all its observations
+//   - Link: a link and its properties, one line of a link file
+//     1.in 2.out 3.is_dest 4.star 5.delay 6.freq 7.ttl 8.monitor 9.firstseen 10.lastseen
+//     and the optional columns, in this order:
+//       monitors: the per-monitor counts of trace2link -M, e.g. 9.0.1.2=3,9.0.1.3=1
+//       loops: the observations in or after a loop, of trace2link --loops=flag, e.g. 2
+//       delays: the DelaySketch of the delay samples, of trace2link --delay other than min
+//       rtt_in, rtt_out: the min RTT to the ingress and the outgress interface, of trace2link --rtt
+//       mpls: the observations with a tunnel at either end, of trace2link --mpls, see mpls::tunnel
+//     the delay is estimated by the DelayEstimator of the header, the min if none
+//   - LinkHeader: the optional first line of a link file, describing its content
+//     #links version=1 tool=trace2link/0.1.0 columns=in,out,... monitors=1.2.3.4,... window=1677196800-1677283200 delay=min
+//     and the LoopStats, if known, loop_policy=drop traces=3000 looping=12 loop_links=40
+//     a file without it is in the legacy format, the same columns without a header
+//   - LoopPolicy, LoopStats: what to do with the links of a looping trace, and how often it happened
+//   - addlink, mergelinks: merge links into a link map
+//   - extract: links between the consecutive hops of a trace
+//   - placeholders, last_hop: links through the anonymous hops of a trace, see trace2link --anon
+//     *SRC_DST_START_TTL: the anonymous router at TTL of a trace, e.g. *9.0.1.2_5.6.7.8_1677196800_4
+//     *end_DST: after the last responsive hop of a trace to DST ending in stars
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+
+use crate::mpls::tunnel;
+use crate::sketch::{DelayEstimator, DelaySketch};
+use crate::trace::{Hop, Trace};
+
+#[derive(Clone, Default, Eq, Hash, PartialEq, PartialOrd, Ord, Debug)]
+pub struct InOut {
+    pub _in: String,
+    pub out: String,
+}
+
+impl InOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LinkProp {
+    pub is_dest: bool,
+    pub star: u32,
+    pub delay: f64,
+    pub freq: u32,
+    pub ttl: u32,
+    pub monitor: String,
+    pub firstseen: u32,
+    pub lastseen: u32,
+    pub monitors: Option<BTreeMap<String, u32>>, // observations per monitor, see -M
+    pub loops: Option<u32>, // observations in or after a loop, see --loops=flag
+    pub delays: Option<DelaySketch>, // the delay samples, see --delay
+    pub rtt: Option<(f64, f64)>,     // min RTT to in and out, see --rtt
+    pub mpls: Option<u32>,           // observations with an MPLS tunnel, see --mpls
+}
+
+impl LinkProp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // start the per-monitor counts, all observations so far are of `monitor`
+    pub fn count_monitors(&mut self) {
+        self.monitors = Some(BTreeMap::from([(self.monitor.clone(), self.freq)]));
+    }
+
+    // start the delay sketch, with the delay of a single observation
+    pub fn sketch_delay(&mut self) {
+        self.delays = Some(DelaySketch::new(self.delay));
+    }
+
+    // the delay column as estimated from the sketch, if any
+    pub fn estimate_delay(&mut self, estimator: DelayEstimator) {
+        if let Some(s) = &self.delays {
+            self.delay = s.estimate(estimator);
+        }
+    }
+
+    // merge another observation of the same link into this one
+    pub fn merge(&mut self, other: &LinkProp) {
+        if !other.is_dest {
+            self.is_dest = false
+        };
+        if self.star > other.star {
+            self.star = other.star
+        };
+        if self.delay > other.delay {
+            self.delay = other.delay
+        };
+        self.freq += other.freq;
+        if self.ttl > other.ttl || (self.ttl == other.ttl && other.monitor < self.monitor) {
+            self.monitor = other.monitor.clone();
+            self.ttl = other.ttl;
+        }
+        if self.firstseen > other.firstseen {
+            self.firstseen = other.firstseen
+        };
+        if self.lastseen < other.lastseen {
+            self.lastseen = other.lastseen
+        };
+        // the counts are only complete if all observations have them
+        match (&mut self.monitors, &other.monitors) {
+            (Some(m), Some(o)) => {
+                for (monitor, n) in o {
+                    *m.entry(monitor.clone()).or_insert(0) += n;
+                }
+            }
+            _ => self.monitors = None,
+        }
+        // a link file without the loops column has no flagged observations
+        self.loops = match (self.loops, other.loops) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0) + b.unwrap_or(0)),
+        };
+        match (&mut self.delays, &other.delays) {
+            (Some(d), Some(o)) => d.merge(o),
+            _ => self.delays = None,
+        }
+        self.rtt = match (self.rtt, other.rtt) {
+            (Some((a, b)), Some((c, d))) => Some((a.min(c), b.min(d))),
+            _ => None,
+        };
+        self.mpls = match (self.mpls, other.mpls) {
+            (Some(a), Some(b)) => Some(a + b),
+            _ => None,
+        };
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Link {
+    pub io: InOut,
+    pub prop: LinkProp,
+}
+
+impl Link {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {:.3} {} {} {} {} {}",
+            self.io._in,
+            self.io.out,
+            if self.prop.is_dest { "Y" } else { "N" },
+            self.prop.star,
+            self.prop.delay,
+            self.prop.freq,
+            self.prop.ttl,
+            self.prop.monitor,
+            self.prop.firstseen,
+            self.prop.lastseen
+        )?;
+        if let Some(m) = &self.prop.monitors {
+            let counts: Vec<String> = m.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            write!(f, " {}", counts.join(","))?;
+        }
+        if let Some(n) = self.prop.loops {
+            write!(f, " {}", n)?;
+        }
+        if let Some(d) = &self.prop.delays {
+            write!(f, " {}", d)?;
+        }
+        if let Some((a, b)) = self.prop.rtt {
+            write!(f, " {:.3} {:.3}", a, b)?;
+        }
+        if let Some(n) = self.prop.mpls {
+            write!(f, " {}", n)?;
+        }
+        Ok(())
+    }
+}
+
+impl Link {
+    // a line of a link file with the given columns, those of its header
+    pub fn parse_columns<S: AsRef<str>>(s: &str, columns: &[S]) -> Result<Self, String> {
+        let f: Vec<&str> = s.split_whitespace().collect();
+        if f.len() != columns.len() {
+            return Err(format!("expected {} fields, got {}", columns.len(), f.len()));
+        }
+        let num = |i: usize| -> Result<u32, String> {
+            f[i].parse::<u32>()
+                .map_err(|_| format!("field {} is not a number: {}", i + 1, f[i]))
+        };
+        let float = |i: usize| -> Result<f64, String> {
+            f[i].parse::<f64>()
+                .map_err(|_| format!("field {} is not a number: {}", i + 1, f[i]))
+        };
+        let mut link = Link {
+            io: InOut {
+                _in: f[0].to_string(),
+                out: f[1].to_string(),
+            },
+            prop: LinkProp {
+                is_dest: match f[2] {
+                    "Y" => true,
+                    "N" => false,
+                    _ => return Err(format!("field 3 is not Y or N: {}", f[2])),
+                },
+                star: num(3)?,
+                delay: float(4)?,
+                freq: num(5)?,
+                ttl: num(6)?,
+                monitor: f[7].to_string(),
+                firstseen: num(8)?,
+                lastseen: num(9)?,
+                ..LinkProp::new()
+            },
+        };
+        let (mut rtt_in, mut rtt_out) = (None, None);
+        for (i, column) in columns.iter().enumerate().skip(LINK_COLUMNS.len()) {
+            match column.as_ref() {
+                MONITORS_COLUMN => link.prop.monitors = Some(parse_monitors(f[i])?),
+                LOOPS_COLUMN => link.prop.loops = Some(num(i)?),
+                DELAYS_COLUMN => link.prop.delays = Some(f[i].parse()?),
+                RTT_IN_COLUMN => rtt_in = Some(float(i)?),
+                RTT_OUT_COLUMN => rtt_out = Some(float(i)?),
+                MPLS_COLUMN => link.prop.mpls = Some(num(i)?),
+                c => return Err(format!("field {} is not a known column: {}", i + 1, c)),
+            }
+        }
+        if let (Some(a), Some(b)) = (rtt_in, rtt_out) {
+            link.prop.rtt = Some((a, b));
+        }
+        Ok(link)
+    }
+}
+
+impl FromStr for Link {
+    type Err = String;
+
+    // a line of a legacy link file, the optional columns told apart by their content
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let f: Vec<&str> = s.split_whitespace().collect();
+        if !LEGACY_FIELDS.contains(&f.len()) {
+            return Err(format!(
+                "expected {} to {} fields, got {}",
+                LEGACY_FIELDS.start(),
+                LEGACY_FIELDS.end(),
+                f.len()
+            ));
+        }
+        let mut columns = LINK_COLUMNS.to_vec();
+        for field in &f[LINK_COLUMNS.len()..] {
+            if field.contains('=') && columns.len() == LINK_COLUMNS.len() {
+                columns.push(MONITORS_COLUMN);
+            } else {
+                columns.push(LOOPS_COLUMN);
+            }
+        }
+        if !is_link_columns(&columns) {
+            return Err(format!("field 12 is not a known column: {}", f[11]));
+        }
+        Link::parse_columns(s, &columns)
+    }
+}
+
+// the 11th field, e.g. 9.0.1.2=3,2001:db8::1=1
+fn parse_monitors(s: &str) -> Result<BTreeMap<String, u32>, String> {
+    s.split(',')
+        .map(|kv| {
+            let (k, v) = kv
+                .rsplit_once('=')
+                .ok_or(format!("field 11 is not monitor=count: {}", kv))?;
+            let n = v
+                .parse::<u32>()
+                .map_err(|_| format!("field 11 count is not a number: {}", kv))?;
+            Ok((k.to_string(), n))
+        })
+        .collect()
+}
+
+// the link file format written by this version
+pub const LINK_SCHEMA_VERSION: u32 = 1;
+pub const LINK_COLUMNS: [&str; 10] = [
+    "in", "out", "is_dest", "star", "delay", "freq", "ttl", "monitor", "firstseen", "lastseen",
+];
+// the fields of a legacy line without a header, LINK_COLUMNS then monitors and loops, see Link::from_str
+pub const LEGACY_FIELDS: std::ops::RangeInclusive<usize> = LINK_COLUMNS.len()..=LINK_COLUMNS.len() + 2;
+// the optional columns after LINK_COLUMNS, any of them in this order
+pub const MONITORS_COLUMN: &str = "monitors";
+pub const LOOPS_COLUMN: &str = "loops";
+pub const DELAYS_COLUMN: &str = "delays";
+pub const RTT_IN_COLUMN: &str = "rtt_in";
+pub const RTT_OUT_COLUMN: &str = "rtt_out";
+pub const MPLS_COLUMN: &str = "mpls";
+pub const OPTIONAL_COLUMNS: [&str; 6] = [
+    MONITORS_COLUMN,
+    LOOPS_COLUMN,
+    DELAYS_COLUMN,
+    RTT_IN_COLUMN,
+    RTT_OUT_COLUMN,
+    MPLS_COLUMN,
+];
+
+// LINK_COLUMNS and some of the OPTIONAL_COLUMNS
+pub fn is_link_columns<S: AsRef<str>>(columns: &[S]) -> bool {
+    if columns.len() < LINK_COLUMNS.len() {
+        return false;
+    }
+    let (fixed, optional) = columns.split_at(LINK_COLUMNS.len());
+    let mut rest = OPTIONAL_COLUMNS.iter();
+    fixed.iter().zip(LINK_COLUMNS).all(|(c, l)| c.as_ref() == l)
+        && optional.iter().all(|c| rest.any(|o| c.as_ref() == *o))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopPolicy {
+    Drop,     // drop all links of the trace
+    Truncate, // keep the links before the first repeated IP
+    Flag,     // keep all links, count the looping ones in the loops column
+}
+
+impl FromStr for LoopPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(LoopPolicy::Drop),
+            "truncate" => Ok(LoopPolicy::Truncate),
+            "flag" => Ok(LoopPolicy::Flag),
+            _ => Err(format!("unknown loop policy {}, use drop, truncate or flag", s)),
+        }
+    }
+}
+
+impl fmt::Display for LoopPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            LoopPolicy::Drop => "drop",
+            LoopPolicy::Truncate => "truncate",
+            LoopPolicy::Flag => "flag",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl LoopPolicy {
+    // apply the policy to the links of a trace, `is_loop` as returned by extract
+    pub fn apply(&self, link: &mut Vec<Link>, is_loop: Option<usize>, stats: &mut LoopStats) {
+        stats.traces += 1;
+        let i = match is_loop {
+            Some(i) => i,
+            None => {
+                if *self == LoopPolicy::Flag {
+                    link.iter_mut().for_each(|l| l.prop.loops = Some(0));
+                }
+                return;
+            }
+        };
+        stats.looping += 1;
+        match self {
+            LoopPolicy::Drop => {
+                stats.links += link.len() as u64;
+                link.clear();
+            }
+            LoopPolicy::Truncate => {
+                stats.links += (link.len() - i) as u64;
+                link.truncate(i);
+            }
+            LoopPolicy::Flag => {
+                stats.links += (link.len() - i) as u64;
+                for (j, l) in link.iter_mut().enumerate() {
+                    l.prop.loops = Some((j >= i) as u32);
+                }
+            }
+        }
+    }
+}
+
+// The traces seen, those with a loop, and the links the policy dropped,
+// truncated or flagged. The policy is None after merging different ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopStats {
+    pub policy: Option<LoopPolicy>,
+    pub traces: u64,
+    pub looping: u64,
+    pub links: u64,
+}
+
+impl LoopStats {
+    pub fn new(policy: LoopPolicy) -> Self {
+        Self {
+            policy: Some(policy),
+            traces: 0,
+            looping: 0,
+            links: 0,
+        }
+    }
+
+    pub fn merge(&mut self, other: &LoopStats) {
+        if self.policy != other.policy {
+            self.policy = None;
+        }
+        self.traces += other.traces;
+        self.looping += other.looping;
+        self.links += other.links;
+    }
+
+    // print the counters to STDERR, if there was a loop
+    pub fn summary(&self, name: &str) {
+        if self.looping > 0 {
+            eprintln!(
+                "{}: {} of {} traces with a loop, {} links {}",
+                name,
+                self.looping,
+                self.traces,
+                self.links,
+                match self.policy {
+                    Some(LoopPolicy::Drop) => "dropped",
+                    Some(LoopPolicy::Truncate) => "truncated",
+                    Some(LoopPolicy::Flag) => "flagged",
+                    None => "dropped, truncated or flagged",
+                }
+            );
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinkHeader {
+    pub version: u32,
+    pub tool: String, // name/version of the generating tool, e.g. trace2link/0.1.0
+    pub columns: Vec<String>,
+    pub monitors: BTreeSet<String>,
+    pub window: Option<(u32, u32)>, // the earliest and the latest trace start time
+    pub loops: Option<LoopStats>,    // None if any input had no counters
+    pub delay: DelayEstimator,       // of the delay column
+}
+
+impl LinkHeader {
+    pub fn new(tool: &str) -> Self {
+        Self {
+            version: LINK_SCHEMA_VERSION,
+            tool: tool.to_string(),
+            columns: LINK_COLUMNS.iter().map(|c| c.to_string()).collect(),
+            monitors: BTreeSet::new(),
+            window: None,
+            loops: None,
+            delay: DelayEstimator::Min,
+        }
+    }
+
+    // with one of the OPTIONAL_COLUMNS, kept in their order
+    pub fn with_column(mut self, column: &str, on: bool) -> Self {
+        if on && !self.has_column(column) {
+            self.columns.push(column.to_string());
+            let order = |c: &String| OPTIONAL_COLUMNS.iter().position(|o| o == c);
+            self.columns[LINK_COLUMNS.len()..].sort_by_key(order);
+        }
+        self
+    }
+
+    pub fn has_column(&self, column: &str) -> bool {
+        self.columns[LINK_COLUMNS.len()..].iter().any(|c| c == column)
+    }
+
+    // a trace seen by the generating tool
+    pub fn observe(&mut self, trace: &Trace) {
+        self.monitors.insert(trace.src.to_string());
+        self.widen(trace.start, trace.start);
+    }
+
+    // the header of an input merged into this one
+    pub fn merge(&mut self, other: &LinkHeader) {
+        self.monitors.extend(other.monitors.iter().cloned());
+        if let Some((a, b)) = other.window {
+            self.widen(a, b);
+        }
+        match (&mut self.loops, &other.loops) {
+            (Some(l), Some(o)) => l.merge(o),
+            _ => self.loops = None,
+        }
+    }
+
+    fn widen(&mut self, a: u32, b: u32) {
+        self.window = match self.window {
+            Some((x, y)) => Some((x.min(a), y.max(b))),
+            None => Some((a, b)),
+        };
+    }
+}
+
+impl fmt::Display for LinkHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#links version={} tool={} columns={} monitors={} window=",
+            self.version,
+            self.tool,
+            self.columns.join(","),
+            self.monitors.iter().cloned().collect::<Vec<_>>().join(",")
+        )?;
+        match self.window {
+            Some((a, b)) => write!(f, "{}-{}", a, b)?,
+            None => write!(f, "-")?,
+        }
+        write!(f, " delay={}", self.delay)?;
+        if let Some(l) = &self.loops {
+            write!(
+                f,
+                " loop_policy={} traces={} looping={} loop_links={}",
+                l.policy.map_or(String::from("mixed"), |p| p.to_string()),
+                l.traces,
+                l.looping,
+                l.links
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for LinkHeader {
+    type Err = String;
+
+    // unknown keys are skipped, for headers of later versions with more fields
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut f = s.split_whitespace();
+        if f.next() != Some("#links") {
+            return Err(String::from("not a link file header, expected #links"));
+        }
+        let mut h = LinkHeader::new("");
+        let mut version = None;
+        let mut loops: Option<LoopStats> = None;
+        for kv in f {
+            let (k, v) = kv
+                .split_once('=')
+                .ok_or(format!("header field is not key=value: {}", kv))?;
+            let list = || v.split(',').filter(|x| !x.is_empty()).map(|x| x.to_string());
+            match k {
+                "version" => {
+                    version = Some(v.parse::<u32>().map_err(|_| format!("bad version: {}", v))?)
+                }
+                "tool" => h.tool = v.to_string(),
+                "columns" => h.columns = list().collect(),
+                "monitors" => h.monitors = list().collect(),
+                "window" if v == "-" => h.window = None,
+                "window" => {
+                    let w = v.split_once('-').and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)));
+                    h.window = Some(w.ok_or(format!("bad window: {}", v))?);
+                }
+                "delay" => h.delay = v.parse()?,
+                "loop_policy" | "traces" | "looping" | "loop_links" => {
+                    let l = loops.get_or_insert(LoopStats::new(LoopPolicy::Drop));
+                    let n = || v.parse::<u64>().map_err(|_| format!("bad {}: {}", k, v));
+                    match k {
+                        "loop_policy" if v == "mixed" => l.policy = None,
+                        "loop_policy" => l.policy = Some(v.parse()?),
+                        "traces" => l.traces = n()?,
+                        "looping" => l.looping = n()?,
+                        _ => l.links = n()?,
+                    }
+                }
+                _ => {}
+            }
+        }
+        h.version = version.ok_or("header without version")?;
+        h.loops = loops;
+        if h.version > LINK_SCHEMA_VERSION {
+            return Err(format!(
+                "link schema version {} is newer than the supported version {}",
+                h.version, LINK_SCHEMA_VERSION
+            ));
+        }
+        if !is_link_columns(&h.columns) {
+            return Err(format!(
+                "columns {} don't match {}[,{}]",
+                h.columns.join(","),
+                LINK_COLUMNS.join(","),
+                OPTIONAL_COLUMNS.join(",")
+            ));
+        }
+        Ok(h)
+    }
+}
+
+// The header of a link file, which is consumed, or None for a legacy file,
+// which is left untouched.
+pub fn read_header(reader: &mut dyn BufRead) -> Result<Option<LinkHeader>, String> {
+    if !reader.fill_buf().map_err(|e| e.to_string())?.starts_with(b"#") {
+        return Ok(None);
+    }
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    line.trim_end().parse().map(Some)
+}
+
+pub fn addlink(link: &Vec<Link>, links: &mut HashMap<InOut, LinkProp>) {
+    for l in link {
+        if links.contains_key(&l.io) {
+            links.get_mut(&l.io).unwrap().merge(&l.prop);
+        } else {
+            links.insert(l.io.clone(), l.prop.clone());
+        }
+    }
+}
+
+// merge a link map, e.g. built by another thread, into `links`
+pub fn mergelinks(other: HashMap<InOut, LinkProp>, links: &mut HashMap<InOut, LinkProp>) {
+    for (io, prop) in other {
+        match links.get_mut(&io) {
+            Some(p) => p.merge(&prop),
+            None => {
+                links.insert(io, prop);
+            }
+        }
+    }
+}
+
+// Links between consecutive responsive hops of a trace, stars inbetween are
+// counted in LinkProp.star, the RTTs of both hops kept in LinkProp.rtt and
+// whether either is in an MPLS tunnel in LinkProp.mpls.
+// Also returns the index of the first link whose outgress interface was
+// already seen in the trace, i.e. where a loop starts.
+pub fn extract(trace: &Trace) -> (Vec<Link>, Option<usize>) {
+    let dest = trace.dst.to_string();
+    let mut node: HashMap<String, bool> = HashMap::new();
+    let mut link: Vec<Link> = Vec::new();
+    let mut is_loop: Option<usize> = None;
+    let mut last: Option<&Hop> = None;
+
+    let mut l = Link::new();
+    l.prop.monitor = trace.src.to_string();
+    for hop in &trace.hops {
+        if let Some(prev) = last {
+            // further replies to the same TTL
+            if prev.probe_ttl == hop.probe_ttl && prev.addr == hop.addr {
+                continue;
+            }
+            if prev.addr != hop.addr {
+                let out = hop.addr.to_string();
+                if node.contains_key(&out) && is_loop.is_none() {
+                    is_loop = Some(link.len());
+                }
+                node.insert(out.clone(), true);
+                l.io._in = prev.addr.to_string();
+                l.io.out = out;
+                l.prop.is_dest = l.io.out == dest;
+                l.prop.star = (hop.probe_ttl as u32).saturating_sub(prev.probe_ttl as u32 + 1);
+                l.prop.delay = (hop.rtt - prev.rtt) / 2.0;
+                l.prop.delay = l.prop.delay.max(0.0);
+                l.prop.rtt = Some((prev.rtt, hop.rtt));
+                l.prop.mpls = Some((tunnel(prev).is_some() || tunnel(hop).is_some()) as u32);
+                l.prop.freq = 1;
+                l.prop.ttl = prev.probe_ttl as u32;
+                l.prop.firstseen = trace.start;
+                l.prop.lastseen = trace.start;
+                link.push(l.clone());
+            }
+        }
+        last = Some(hop);
+    }
+    (link, is_loop)
+}
+
+// the placeholder node of the anonymous hop at `ttl` of a trace
+pub fn placeholder(trace: &Trace, ttl: u32) -> String {
+    format!("*{}_{}_{}_{}", trace.src, trace.dst, trace.start, ttl)
+}
+
+// a placeholder node of an anonymous hop or of a trace ending in stars
+pub fn is_placeholder(node: &str) -> bool {
+    node.starts_with('*')
+}
+
+// Links through the anonymous hops of the links of a trace with stars inbetween,
+// in -> *ttl+1 -> ... -> *ttl+star -> out, each with no stars. Their delays are
+// unknown, 0, and so is the RTT of a placeholder, NaN.
+pub fn placeholders(trace: &Trace, link: &[Link]) -> Vec<Link> {
+    let mut anon = Vec::new();
+    for l in link.iter().filter(|l| l.prop.star > 0) {
+        let mut nodes = vec![l.io._in.clone()];
+        nodes.extend((1..=l.prop.star).map(|i| placeholder(trace, l.prop.ttl + i)));
+        nodes.push(l.io.out.clone());
+        for (i, pair) in nodes.windows(2).enumerate() {
+            let mut p = l.clone();
+            p.io._in = pair[0].clone();
+            p.io.out = pair[1].clone();
+            p.prop.is_dest = l.prop.is_dest && i + 2 == nodes.len();
+            p.prop.star = 0;
+            p.prop.delay = 0.0;
+            p.prop.ttl = l.prop.ttl + i as u32;
+            p.prop.rtt = l.prop.rtt.map(|(a, b)| {
+                let first = if i == 0 { a } else { f64::NAN };
+                let last = if i + 2 == nodes.len() { b } else { f64::NAN };
+                (first, last)
+            });
+            anon.push(p);
+        }
+    }
+    anon
+}
+
+// The link from the last responsive hop of a trace ending in stars to *end_DST,
+// with the trailing stars in LinkProp.star. None if the trace reached its
+// destination or has no stars after its last reply.
+pub fn last_hop(trace: &Trace) -> Option<Link> {
+    let last = trace.hops.last()?;
+    if last.addr == trace.dst || trace.hop_count <= last.probe_ttl as u16 {
+        return None;
+    }
+    let mut l = Link::new();
+    l.io._in = last.addr.to_string();
+    l.io.out = format!("*end_{}", trace.dst);
+    l.prop.star = (trace.hop_count - last.probe_ttl as u16) as u32;
+    l.prop.freq = 1;
+    l.prop.ttl = last.probe_ttl as u32;
+    l.prop.monitor = trace.src.to_string();
+    l.prop.firstseen = trace.start;
+    l.prop.lastseen = trace.start;
+    l.prop.rtt = Some((last.rtt, f64::NAN));
+    l.prop.mpls = Some(tunnel(last).is_some() as u32);
+    Some(l)
+}
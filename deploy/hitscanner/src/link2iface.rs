@@ -1,34 +1,46 @@
 // trace2link -- extract Router IPs from a links file
 // =============================================================================
-// USAGE: trace2link <$path_to_links_file>
-// INPUT: a links file from STDIN or @ARGV
-// OUTPUT: a list Router IPs
+// USAGE: trace2link [--compress none|gzip|zstd|xz|bz2] <$path_to_links_file>
+// INPUT: a links file from STDIN or @ARGV, plain or gzip/zstd/xz/bz2 compressed,
+//        with or without the #links header line, see link::LinkHeader
+// OUTPUT: a list Router IPs, without the placeholders of trace2link --anon
 
+use hitscanner::compress::{self, Codec};
+use hitscanner::link::{is_placeholder, read_header};
 use itertools::Itertools;
-use std::io::{BufRead, BufReader, Read};
+use std::io::{BufRead, Write};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-fn openfile(path: &std::path::PathBuf) -> BufReader<Box<dyn Read>> {
-    let input: Box<dyn std::io::Read + 'static> = if path.as_os_str() == "-" {
-        Box::new(std::io::stdin())
-    } else {
-        Box::new(std::fs::File::open(&path).unwrap())
-    };
-
-    let reader: BufReader<Box<dyn Read>> = BufReader::new(input);
-
-    return reader;
+fn openfile(path: &Path) -> Box<dyn BufRead> {
+    match compress::open(path) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error: {}: {}.", path.display(), e);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn main(){
     let mut pargs = pico_args::Arguments::from_env();
-    let input = match pargs.free_from_str::<PathBuf>() {
+    let codec: Codec = match pargs.opt_value_from_str("--compress") {
+        Ok(v) => v.unwrap_or(Codec::Plain),
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    let input = match pargs.free_from_os_str(|s| Ok::<PathBuf, &str>(s.into())) {
         Ok(v) => v,
         Err(_) => PathBuf::from("-"),
     };
 
-    let file = openfile(&input);
+    let mut file = openfile(&input);
+    if let Err(e) = read_header(&mut file) {
+        eprintln!("Error: {}:1: {}.", input.display(), e);
+        std::process::exit(1);
+    }
     let mut out: HashMap<String, bool> = HashMap::new();
     let mut router = HashSet::new();
     for line in file.lines() {
@@ -46,7 +58,9 @@ fn main(){
             router.insert(i.to_string());
         }
     }
-    for i in router.iter().sorted() {
-        println!("{}", i);
+    let mut output = compress::create(&PathBuf::from("-"), codec).unwrap();
+    for i in router.iter().filter(|i| !is_placeholder(i)).sorted() {
+        writeln!(output, "{}", i).unwrap();
     }
+    output.finish().unwrap();
 }
\ No newline at end of file
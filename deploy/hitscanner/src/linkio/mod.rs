@@ -0,0 +1,912 @@
+// Link files in text and typed formats, see --format of trace2link and linkmerge
+//   - LinkFormat: csv, jsonl, parquet or arrow
+//     - csv: the text format of link::Link with the #links header, compressed by --compress
+//     - jsonl: one JSON object per link, IPs as text, compressed by --compress,
+//       for other tools only, without the header it can't be read back
+//     - parquet: typed columns, zstd compressed within the file
+//     - arrow: typed columns in an Arrow IPC file, uncompressed
+//   - create: a LinkWriter of one of the formats, "-" for STDOUT
+//   - open: a LinkSource, text lines or the links of a parquet/arrow file,
+//     detected by content like the compression, a jsonl file is an error
+// The typed columns are named as link::LINK_COLUMNS:
+//   in, out, monitor: UInt32 if all IPs are known to be IPv4 before the first
+//                     batch, otherwise FixedSizeBinary(16), e.g. linkmerge of text inputs
+//                     with an IPv6 link, the 128-bit address big-endian, IPv4 as ::ffff:a.b.c.d
+//   is_dest: Boolean, delay: Float64, star, freq, ttl, firstseen, lastseen: UInt32
+//   and the optional columns of the links:
+//   monitors: Map of monitor IP to UInt32 count, loops: UInt32,
+//   delays: Struct of the DelaySketch, min, max: Float64, zeros: UInt32 and
+//           bins: Map of Int32 bin to UInt32 count,
+//   rtt_in, rtt_out: Float64, mpls: UInt32
+// the #links header is kept in the schema metadata under HEADER_KEY.
+
+use crate::compress::{self, Codec};
+use crate::link::{
+    is_link_columns, LinkHeader, DELAYS_COLUMN, LINK_COLUMNS, LOOPS_COLUMN, MONITORS_COLUMN, MPLS_COLUMN,
+    OPTIONAL_COLUMNS, RTT_IN_COLUMN, RTT_OUT_COLUMN,
+};
+use crate::sketch::DelaySketch;
+use crate::Link;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{IpAddr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow::array::{
+    Array, ArrayBuilder, ArrayRef, BooleanArray, FixedSizeBinaryArray, FixedSizeBinaryBuilder,
+    Float64Array, Int32Array, Int32Builder, MapArray, MapBuilder, RecordBatch, StructArray, UInt32Array,
+    UInt32Builder,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use bytes::Bytes;
+use serde::Serialize;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+
+// schema metadata key of the #links header
+pub const HEADER_KEY: &str = "links_header";
+
+// rows of a record batch
+const BATCH: usize = 8192;
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const ARROW_MAGIC: &[u8] = b"ARROW1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+    Arrow,
+}
+
+impl LinkFormat {
+    // file name suffix after .links, e.g. ".parquet"
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            LinkFormat::Csv => "",
+            LinkFormat::Jsonl => ".jsonl",
+            LinkFormat::Parquet => ".parquet",
+            LinkFormat::Arrow => ".arrow",
+        }
+    }
+
+    // parquet and arrow files are not wrapped in --compress
+    pub fn is_columnar(&self) -> bool {
+        matches!(self, LinkFormat::Parquet | LinkFormat::Arrow)
+    }
+}
+
+impl FromStr for LinkFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" | "text" => Ok(LinkFormat::Csv),
+            "jsonl" | "json" => Ok(LinkFormat::Jsonl),
+            "parquet" => Ok(LinkFormat::Parquet),
+            "arrow" | "ipc" => Ok(LinkFormat::Arrow),
+            _ => Err(format!("unknown format {}, use csv, jsonl, parquet or arrow", s)),
+        }
+    }
+}
+
+impl fmt::Display for LinkFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            LinkFormat::Csv => "csv",
+            LinkFormat::Jsonl => "jsonl",
+            LinkFormat::Parquet => "parquet",
+            LinkFormat::Arrow => "arrow",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// the schema of the typed formats
+fn schema(ipv6: bool, optional: &[&str], header: Option<&LinkHeader>) -> SchemaRef {
+    let ip = if ipv6 { DataType::FixedSizeBinary(16) } else { DataType::UInt32 };
+    let mut fields: Vec<Field> = LINK_COLUMNS
+        .iter()
+        .map(|&c| {
+            let t = match c {
+                "in" | "out" | "monitor" => ip.clone(),
+                "is_dest" => DataType::Boolean,
+                "delay" => DataType::Float64,
+                _ => DataType::UInt32,
+            };
+            Field::new(c, t, false)
+        })
+        .collect();
+    for &c in optional {
+        let t = match c {
+            // the type of the column as built by monitors_array
+            MONITORS_COLUMN => monitors_array(&[], ipv6).unwrap().data_type().clone(),
+            DELAYS_COLUMN => delays_array(&[]).unwrap().data_type().clone(),
+            RTT_IN_COLUMN | RTT_OUT_COLUMN => DataType::Float64,
+            _ => DataType::UInt32,
+        };
+        fields.push(Field::new(c, t, false));
+    }
+    let mut metadata = HashMap::new();
+    if let Some(h) = header {
+        metadata.insert(HEADER_KEY.to_string(), h.to_string());
+    }
+    Arc::new(Schema::new_with_metadata(fields, metadata))
+}
+
+fn parse_ip(s: &str) -> io::Result<IpAddr> {
+    s.parse().map_err(|_| invalid(format!("not an IP address: {}", s)))
+}
+
+fn ip_octets(ip: &IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(a) => a.to_ipv6_mapped().octets(),
+        IpAddr::V6(a) => a.octets(),
+    }
+}
+
+fn ip_u32(ip: &IpAddr) -> io::Result<u32> {
+    match ip {
+        IpAddr::V4(a) => Ok(u32::from(*a)),
+        IpAddr::V6(a) => Err(invalid(format!(
+            "IPv6 address {} after IPv4 links, the file stores IPv4 as UInt32, use --format csv",
+            a
+        ))),
+    }
+}
+
+fn ip_array(ips: &[IpAddr], ipv6: bool) -> io::Result<ArrayRef> {
+    if ipv6 {
+        let v = ips.iter().map(ip_octets);
+        Ok(Arc::new(FixedSizeBinaryArray::try_from_iter(v).map_err(io::Error::other)?))
+    } else {
+        let v: io::Result<Vec<u32>> = ips.iter().map(ip_u32).collect();
+        Ok(Arc::new(UInt32Array::from(v?)))
+    }
+}
+
+// the IP at row `i` of an ip_array, IPv4-mapped addresses as IPv4
+fn ip_at(array: &dyn Array, i: usize, ipv6: bool) -> String {
+    if ipv6 {
+        let a = array.as_any().downcast_ref::<FixedSizeBinaryArray>().unwrap();
+        let ip = Ipv6Addr::from(<[u8; 16]>::try_from(a.value(i)).unwrap());
+        match ip.to_ipv4_mapped() {
+            Some(v4) => v4.to_string(),
+            None => ip.to_string(),
+        }
+    } else {
+        let a = array.as_any().downcast_ref::<UInt32Array>().unwrap();
+        std::net::Ipv4Addr::from(a.value(i)).to_string()
+    }
+}
+
+fn map_array<K: ArrayBuilder>(
+    links: &[Link],
+    keys: K,
+    push: impl Fn(&mut K, &IpAddr) -> io::Result<()>,
+) -> io::Result<ArrayRef> {
+    let mut b = MapBuilder::new(None, keys, UInt32Builder::new());
+    for l in links {
+        let counts = l
+            .prop
+            .monitors
+            .as_ref()
+            .ok_or_else(|| invalid(format!("link {} {} without per-monitor counts", l.io._in, l.io.out)))?;
+        for (monitor, n) in counts {
+            push(b.keys(), &parse_ip(monitor)?)?;
+            b.values().append_value(*n);
+        }
+        b.append(true).map_err(io::Error::other)?;
+    }
+    Ok(Arc::new(b.finish()))
+}
+
+fn monitors_array(links: &[Link], ipv6: bool) -> io::Result<ArrayRef> {
+    if ipv6 {
+        map_array(links, FixedSizeBinaryBuilder::new(16), |b, ip| {
+            b.append_value(ip_octets(ip)).map_err(io::Error::other)
+        })
+    } else {
+        map_array(links, UInt32Builder::new(), |b, ip| {
+            b.append_value(ip_u32(ip)?);
+            Ok(())
+        })
+    }
+}
+
+fn delays_array(links: &[Link]) -> io::Result<ArrayRef> {
+    let (mut min, mut max, mut zeros) = (Vec::new(), Vec::new(), Vec::new());
+    let mut bins = MapBuilder::new(None, Int32Builder::new(), UInt32Builder::new());
+    for l in links {
+        let d = l
+            .prop
+            .delays
+            .as_ref()
+            .ok_or_else(|| invalid(format!("link {} {} without delay sketch", l.io._in, l.io.out)))?;
+        min.push(d.min);
+        max.push(d.max);
+        zeros.push(d.zeros);
+        for (b, n) in &d.bins {
+            bins.keys().append_value(*b);
+            bins.values().append_value(*n);
+        }
+        bins.append(true).map_err(io::Error::other)?;
+    }
+    let bins = bins.finish();
+    let field = |name: &str, t: &DataType| Arc::new(Field::new(name, t.clone(), false));
+    Ok(Arc::new(StructArray::from(vec![
+        (field("min", &DataType::Float64), Arc::new(Float64Array::from(min)) as ArrayRef),
+        (field("max", &DataType::Float64), Arc::new(Float64Array::from(max)) as ArrayRef),
+        (field("zeros", &DataType::UInt32), Arc::new(UInt32Array::from(zeros)) as ArrayRef),
+        (field("bins", bins.data_type()), Arc::new(bins) as ArrayRef),
+    ])))
+}
+
+// the DelaySketch at row `i` of a delays_array
+fn delays_at(array: &dyn Array, i: usize) -> Result<DelaySketch, String> {
+    let a = array.as_any().downcast_ref::<StructArray>().unwrap();
+    let f64 = |c: usize| a.column(c).as_any().downcast_ref::<Float64Array>().unwrap().value(i);
+    let map = a.column(3).as_any().downcast_ref::<MapArray>().unwrap();
+    let entries = map.value(i);
+    let keys = entries.column(0).as_any().downcast_ref::<Int32Array>().unwrap();
+    let counts = entries.column(1).as_any().downcast_ref::<UInt32Array>().unwrap();
+    let sketch = DelaySketch {
+        min: f64(0),
+        max: f64(1),
+        zeros: a.column(2).as_any().downcast_ref::<UInt32Array>().unwrap().value(i),
+        bins: (0..entries.len()).map(|j| (keys.value(j), counts.value(j))).collect(),
+    };
+    // a sketch has at least the sample of one observation, as in the text form
+    if sketch.is_empty() {
+        return Err(format!("empty delay sketch: {}", sketch));
+    }
+    Ok(sketch)
+}
+
+fn record_batch(links: &[Link], schema: &SchemaRef, ipv6: bool) -> io::Result<RecordBatch> {
+    let ips = |f: &dyn Fn(&Link) -> &str| -> io::Result<ArrayRef> {
+        let v: io::Result<Vec<IpAddr>> = links.iter().map(|l| parse_ip(f(l))).collect();
+        ip_array(&v?, ipv6)
+    };
+    let u32s = |f: &dyn Fn(&Link) -> u32| -> ArrayRef {
+        Arc::new(UInt32Array::from(links.iter().map(f).collect::<Vec<_>>()))
+    };
+    let f64s = |f: &dyn Fn(&Link) -> f64| -> ArrayRef {
+        Arc::new(Float64Array::from(links.iter().map(f).collect::<Vec<_>>()))
+    };
+    let mut columns: Vec<ArrayRef> = vec![
+        ips(&|l| &l.io._in)?,
+        ips(&|l| &l.io.out)?,
+        Arc::new(BooleanArray::from(links.iter().map(|l| l.prop.is_dest).collect::<Vec<_>>())),
+        u32s(&|l| l.prop.star),
+        f64s(&|l| l.prop.delay),
+        u32s(&|l| l.prop.freq),
+        u32s(&|l| l.prop.ttl),
+        ips(&|l| &l.prop.monitor)?,
+        u32s(&|l| l.prop.firstseen),
+        u32s(&|l| l.prop.lastseen),
+    ];
+    for f in &schema.fields()[LINK_COLUMNS.len()..] {
+        columns.push(match f.name().as_str() {
+            MONITORS_COLUMN => monitors_array(links, ipv6)?,
+            DELAYS_COLUMN => delays_array(links)?,
+            RTT_IN_COLUMN => f64s(&|l| l.prop.rtt.map_or(0.0, |r| r.0)),
+            RTT_OUT_COLUMN => f64s(&|l| l.prop.rtt.map_or(0.0, |r| r.1)),
+            MPLS_COLUMN => u32s(&|l| l.prop.mpls.unwrap_or(0)),
+            _ => u32s(&|l| l.prop.loops.unwrap_or(0)),
+        });
+    }
+    RecordBatch::try_new(schema.clone(), columns).map_err(io::Error::other)
+}
+
+enum Columnar {
+    Parquet(ArrowWriter<Box<dyn Write + Send>>),
+    Arrow(FileWriter<Box<dyn Write + Send>>),
+}
+
+impl Columnar {
+    fn write(&mut self, batch: &RecordBatch) -> io::Result<()> {
+        match self {
+            Columnar::Parquet(w) => w.write(batch).map_err(io::Error::other),
+            Columnar::Arrow(w) => w.write(batch).map_err(io::Error::other),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Columnar::Parquet(w) => w.into_inner().map_err(io::Error::other)?.flush(),
+            Columnar::Arrow(w) => w.into_inner().map_err(io::Error::other)?.flush(),
+        }
+    }
+}
+
+// links are buffered into record batches, the schema is fixed by the first batch,
+// unless the caller gave the columns, e.g. by the header, and IPv6 up front
+struct Batches {
+    format: LinkFormat,
+    header: Option<LinkHeader>,
+    ipv6: bool,
+    optional: Option<Vec<String>>, // the optional columns of the schema
+    out: Option<Box<dyn Write + Send>>,
+    writer: Option<(Columnar, SchemaRef)>,
+    links: Vec<Link>,
+}
+
+impl Batches {
+    fn flush(&mut self) -> io::Result<()> {
+        if self.writer.is_none() {
+            self.ipv6 = self.ipv6
+                || self.links.iter().any(|l| {
+                    [&l.io._in, &l.io.out, &l.prop.monitor].iter().any(|s| s.contains(':'))
+                });
+            let optional: Vec<&str> = match (&self.optional, self.links.first()) {
+                (Some(columns), _) => columns.iter().map(|c| c.as_str()).collect(),
+                (None, Some(l)) => OPTIONAL_COLUMNS
+                    .into_iter()
+                    .filter(|&c| match c {
+                        MONITORS_COLUMN => l.prop.monitors.is_some(),
+                        LOOPS_COLUMN => l.prop.loops.is_some(),
+                        DELAYS_COLUMN => l.prop.delays.is_some(),
+                        MPLS_COLUMN => l.prop.mpls.is_some(),
+                        _ => l.prop.rtt.is_some(),
+                    })
+                    .collect(),
+                (None, None) => Vec::new(),
+            };
+            let schema = schema(self.ipv6, &optional, self.header.as_ref());
+            let out = self.out.take().unwrap();
+            let w = match self.format {
+                LinkFormat::Parquet => {
+                    let props = WriterProperties::builder()
+                        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                        .build();
+                    Columnar::Parquet(ArrowWriter::try_new(out, schema.clone(), Some(props)).map_err(io::Error::other)?)
+                }
+                _ => Columnar::Arrow(FileWriter::try_new(out, &schema).map_err(io::Error::other)?),
+            };
+            self.writer = Some((w, schema));
+        }
+        if !self.links.is_empty() {
+            let (w, schema) = self.writer.as_mut().unwrap();
+            w.write(&record_batch(&self.links, schema, self.ipv6)?)?;
+            self.links.clear();
+        }
+        Ok(())
+    }
+}
+
+// a line of the jsonl format, the keys in the order of LINK_COLUMNS
+#[derive(Serialize)]
+struct JsonLink<'a> {
+    #[serde(rename = "in")]
+    _in: &'a str,
+    out: &'a str,
+    is_dest: bool,
+    star: u32,
+    delay: f64,
+    freq: u32,
+    ttl: u32,
+    monitor: &'a str,
+    firstseen: u32,
+    lastseen: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    monitors: Option<&'a BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loops: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delays: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_in: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rtt_out: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mpls: Option<u32>,
+}
+
+enum Sink {
+    Csv(compress::Writer),
+    Jsonl(compress::Writer),
+    Batches(Box<Batches>),
+}
+
+// A writer of links in one format, call finish to complete the file.
+pub struct LinkWriter(Sink);
+
+impl LinkWriter {
+    // IPv6 columns from the start, when the caller knows there are IPv6 links
+    pub fn ipv6(mut self, ipv6: bool) -> Self {
+        if let Sink::Batches(b) = &mut self.0 {
+            b.ipv6 = ipv6;
+        }
+        self
+    }
+
+    // the columns of the links, the optional ones among them make the schema
+    // even before, or without, the first link
+    pub fn columns(mut self, columns: &[String]) -> Self {
+        if let Sink::Batches(b) = &mut self.0 {
+            let optional = columns.iter().filter(|c| OPTIONAL_COLUMNS.contains(&c.as_str()));
+            b.optional = Some(optional.cloned().collect());
+        }
+        self
+    }
+
+    pub fn write(&mut self, link: &Link) -> io::Result<()> {
+        match &mut self.0 {
+            Sink::Csv(w) => writeln!(w, "{}", link),
+            Sink::Jsonl(w) => {
+                let record = JsonLink {
+                    _in: &link.io._in,
+                    out: &link.io.out,
+                    is_dest: link.prop.is_dest,
+                    star: link.prop.star,
+                    delay: link.prop.delay,
+                    freq: link.prop.freq,
+                    ttl: link.prop.ttl,
+                    monitor: &link.prop.monitor,
+                    firstseen: link.prop.firstseen,
+                    lastseen: link.prop.lastseen,
+                    monitors: link.prop.monitors.as_ref(),
+                    loops: link.prop.loops,
+                    delays: link.prop.delays.as_ref().map(|d| d.to_string()),
+                    rtt_in: link.prop.rtt.map(|r| r.0),
+                    rtt_out: link.prop.rtt.map(|r| r.1),
+                    mpls: link.prop.mpls,
+                };
+                writeln!(w, "{}", serde_json::to_string(&record).unwrap())
+            }
+            Sink::Batches(b) => {
+                b.links.push(link.clone());
+                if b.links.len() >= BATCH {
+                    b.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.0 {
+            Sink::Csv(w) | Sink::Jsonl(w) => w.finish(),
+            Sink::Batches(mut b) => {
+                b.flush()?;
+                b.writer.take().unwrap().0.finish()
+            }
+        }
+    }
+}
+
+// "-" for STDOUT, the header is written unless None,
+// the codec must be Plain for parquet and arrow
+pub fn create(
+    path: &Path,
+    format: LinkFormat,
+    codec: Codec,
+    header: Option<&LinkHeader>,
+) -> io::Result<LinkWriter> {
+    let w = LinkWriter(match format {
+        LinkFormat::Csv => {
+            let mut w = compress::create(path, codec)?;
+            if let Some(h) = header {
+                writeln!(w, "{}", h)?;
+            }
+            Sink::Csv(w)
+        }
+        LinkFormat::Jsonl => Sink::Jsonl(compress::create(path, codec)?),
+        LinkFormat::Parquet | LinkFormat::Arrow => {
+            if codec != Codec::Plain {
+                return Err(invalid(format!("{} can't be compressed with {}", format, codec)));
+            }
+            let out: Box<dyn Write + Send> = if path.as_os_str() == "-" {
+                Box::new(io::stdout())
+            } else {
+                Box::new(File::create(path)?)
+            };
+            Sink::Batches(Box::new(Batches {
+                format,
+                header: header.cloned(),
+                ipv6: false,
+                optional: None,
+                out: Some(out),
+                writer: None,
+                links: Vec::new(),
+            }))
+        }
+    });
+    Ok(match header {
+        Some(h) => w.columns(&h.columns),
+        None => w,
+    })
+}
+
+// The links of a parquet or arrow file, one record batch in memory.
+pub struct ColumnarReader {
+    batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>,
+    batch: Option<RecordBatch>,
+    row: usize,
+    pub header: Option<LinkHeader>, // None for a file without the header metadata
+    pub ipv6: bool,
+    pub optional: Vec<String>, // the OPTIONAL_COLUMNS of the file
+}
+
+impl ColumnarReader {
+    fn new(
+        schema: SchemaRef,
+        batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>>,
+    ) -> io::Result<Self> {
+        let names: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+        if !is_link_columns(&names) {
+            return Err(invalid(format!(
+                "columns {} don't match {}[,{}]",
+                names.join(","),
+                LINK_COLUMNS.join(","),
+                OPTIONAL_COLUMNS.join(",")
+            )));
+        }
+        let header = match schema.metadata().get(HEADER_KEY) {
+            Some(h) => Some(h.parse::<LinkHeader>().map_err(invalid)?),
+            None => None,
+        };
+        let ipv6 = schema.field(0).data_type() == &DataType::FixedSizeBinary(16);
+        // the batches are checked against the expected schema before any row is read
+        let optional = &names[LINK_COLUMNS.len()..];
+        let expected = self::schema(ipv6, optional, None);
+        for (f, e) in schema.fields().iter().zip(expected.fields()) {
+            if f.data_type() != e.data_type() {
+                return Err(invalid(format!(
+                    "column {} is {}, expected {}",
+                    f.name(),
+                    f.data_type(),
+                    e.data_type()
+                )));
+            }
+        }
+        Ok(ColumnarReader {
+            batches,
+            batch: None,
+            row: 0,
+            header,
+            ipv6,
+            optional: optional.iter().map(|c| c.to_string()).collect(),
+        })
+    }
+
+    fn ip(&self, batch: &RecordBatch, column: usize) -> String {
+        ip_at(batch.column(column), self.row, self.ipv6)
+    }
+
+    fn link(&self, batch: &RecordBatch) -> Result<Link, String> {
+        let u32 = |c: usize| batch.column(c).as_any().downcast_ref::<UInt32Array>().unwrap().value(self.row);
+        let f64 = |c: usize| batch.column(c).as_any().downcast_ref::<Float64Array>().unwrap().value(self.row);
+        let mut link = Link::new();
+        link.io._in = self.ip(batch, 0);
+        link.io.out = self.ip(batch, 1);
+        link.prop.is_dest = batch.column(2).as_any().downcast_ref::<BooleanArray>().unwrap().value(self.row);
+        link.prop.star = u32(3);
+        link.prop.delay = f64(4);
+        link.prop.freq = u32(5);
+        link.prop.ttl = u32(6);
+        link.prop.monitor = self.ip(batch, 7);
+        link.prop.firstseen = u32(8);
+        link.prop.lastseen = u32(9);
+        for (c, name) in self.optional.iter().enumerate().map(|(i, n)| (LINK_COLUMNS.len() + i, n)) {
+            if name == MONITORS_COLUMN {
+                let map = batch.column(c).as_any().downcast_ref::<MapArray>().unwrap();
+                let entries = map.value(self.row);
+                let counts = entries.column(1).as_any().downcast_ref::<UInt32Array>().unwrap();
+                link.prop.monitors = Some(
+                    (0..entries.len())
+                        .map(|i| (ip_at(entries.column(0), i, self.ipv6), counts.value(i)))
+                        .collect(),
+                );
+            } else if name == LOOPS_COLUMN {
+                link.prop.loops = Some(u32(c));
+            } else if name == DELAYS_COLUMN {
+                link.prop.delays = Some(delays_at(batch.column(c), self.row)?);
+            } else if name == RTT_IN_COLUMN {
+                link.prop.rtt = Some((f64(c), link.prop.rtt.map_or(0.0, |r| r.1)));
+            } else if name == RTT_OUT_COLUMN {
+                link.prop.rtt = Some((link.prop.rtt.map_or(0.0, |r| r.0), f64(c)));
+            } else if name == MPLS_COLUMN {
+                link.prop.mpls = Some(u32(c));
+            }
+        }
+        Ok(link)
+    }
+}
+
+impl Iterator for ColumnarReader {
+    type Item = Result<Link, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(batch) = &self.batch {
+                if self.row < batch.num_rows() {
+                    if (0..batch.num_columns()).any(|c| batch.column(c).is_null(self.row)) {
+                        self.row += 1;
+                        return Some(Err(String::from("null value")));
+                    }
+                    let link = self.link(batch);
+                    self.row += 1;
+                    return Some(link);
+                }
+            }
+            match self.batches.next()? {
+                Ok(b) => {
+                    self.batch = Some(b);
+                    self.row = 0;
+                }
+                Err(e) => return Some(Err(e.to_string())),
+            }
+        }
+    }
+}
+
+fn parquet<R: parquet::file::reader::ChunkReader + 'static>(read: R) -> io::Result<ColumnarReader> {
+    let builder = ParquetRecordBatchReaderBuilder::try_new(read).map_err(io::Error::other)?;
+    let schema = builder.schema().clone();
+    let reader = builder.with_batch_size(BATCH).build().map_err(io::Error::other)?;
+    ColumnarReader::new(schema, Box::new(reader))
+}
+
+fn arrow<R: Read + io::Seek + 'static>(read: R) -> io::Result<ColumnarReader> {
+    let reader = FileReader::try_new(read, None).map_err(io::Error::other)?;
+    ColumnarReader::new(reader.schema(), Box::new(reader))
+}
+
+fn is_columnar(magic: &[u8]) -> bool {
+    magic.starts_with(PARQUET_MAGIC) || magic.starts_with(ARROW_MAGIC)
+}
+
+pub enum LinkSource {
+    Text(Box<dyn BufRead>), // csv lines, the header is not read yet
+    Columnar(Box<ColumnarReader>),
+}
+
+// "-" for STDIN. An uncompressed parquet/arrow file is read in place,
+// one on STDIN or within gzip etc. is read into memory first.
+pub fn open(path: &Path) -> io::Result<LinkSource> {
+    if path.as_os_str() != "-" {
+        let mut magic = [0u8; 6];
+        let mut file = File::open(path)?;
+        let n = file.read(&mut magic)?;
+        if magic[..n].starts_with(PARQUET_MAGIC) {
+            return Ok(LinkSource::Columnar(Box::new(parquet(File::open(path)?)?)));
+        } else if magic[..n].starts_with(ARROW_MAGIC) {
+            return Ok(LinkSource::Columnar(Box::new(arrow(File::open(path)?)?)));
+        }
+    }
+    let mut reader = compress::open(path)?;
+    let head = reader.fill_buf()?;
+    if head.starts_with(b"{") {
+        return Err(invalid(String::from(
+            "jsonl links can't be read back, write them with --format csv, parquet or arrow",
+        )));
+    }
+    if !is_columnar(head) {
+        return Ok(LinkSource::Text(reader));
+    }
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    if buf.starts_with(PARQUET_MAGIC) {
+        Ok(LinkSource::Columnar(Box::new(parquet(Bytes::from(buf))?)))
+    } else {
+        Ok(LinkSource::Columnar(Box::new(arrow(io::Cursor::new(buf))?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::link::{read_header, LINK_SCHEMA_VERSION};
+    use crate::sketch::DelaySketch;
+
+    fn scratch(test: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("hitscanner-linkio-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn header() -> LinkHeader {
+        let mut h = LinkHeader::new("trace2link/0.1.0");
+        for c in OPTIONAL_COLUMNS {
+            h = h.with_column(c, true);
+        }
+        h.window = Some((1677196800, 1677283200));
+        h
+    }
+
+    // a link with every optional column
+    fn link(i: &str, o: &str, monitor: &str) -> Link {
+        let mut l = Link::new();
+        l.io._in = i.to_string();
+        l.io.out = o.to_string();
+        l.prop.is_dest = true;
+        l.prop.star = 1;
+        l.prop.delay = 0.25;
+        l.prop.freq = 3;
+        l.prop.ttl = 7;
+        l.prop.monitor = monitor.to_string();
+        l.prop.firstseen = 1677196800;
+        l.prop.lastseen = 1677283200;
+        l.prop.monitors = Some(BTreeMap::from([(monitor.to_string(), 2), ("192.0.2.9".to_string(), 1)]));
+        l.prop.loops = Some(1);
+        let mut d = DelaySketch::new(0.25);
+        d.merge(&DelaySketch::new(3.75));
+        l.prop.delays = Some(d);
+        l.prop.rtt = Some((1.5, 2.0));
+        l.prop.mpls = Some(2);
+        l
+    }
+
+    fn write(path: &Path, format: LinkFormat, ipv6: bool, links: &[Link]) {
+        let mut w = create(path, format, Codec::Plain, Some(&header())).unwrap().ipv6(ipv6);
+        for l in links {
+            w.write(l).unwrap();
+        }
+        w.finish().unwrap();
+    }
+
+    fn read_columnar(path: &Path) -> Box<ColumnarReader> {
+        match open(path).unwrap() {
+            LinkSource::Columnar(r) => r,
+            LinkSource::Text(_) => panic!("{} read as text", path.display()),
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        for f in [LinkFormat::Csv, LinkFormat::Jsonl, LinkFormat::Parquet, LinkFormat::Arrow] {
+            assert_eq!(f.to_string().parse::<LinkFormat>(), Ok(f));
+        }
+        assert!("tsv".parse::<LinkFormat>().is_err());
+        assert!(LinkFormat::Arrow.is_columnar() && !LinkFormat::Jsonl.is_columnar());
+    }
+
+    #[test]
+    fn typed_round_trip() {
+        let dir = scratch("typed");
+        let v4 = vec![link("10.0.0.1", "10.0.0.2", "192.0.2.1"), link("10.0.0.2", "10.0.0.3", "192.0.2.1")];
+        let mut mixed = v4.clone();
+        mixed.push(link("2001:db8::1", "2001:db8::2", "2001:db8::ff"));
+        for format in [LinkFormat::Parquet, LinkFormat::Arrow] {
+            for (links, ipv6) in [(&v4, false), (&mixed, true)] {
+                let path = dir.join(format!("links{}{}", ipv6, format.suffix()));
+                write(&path, format, ipv6, links);
+                let r = read_columnar(&path);
+                assert_eq!(r.ipv6, ipv6);
+                assert_eq!(r.header.as_ref(), Some(&header()));
+                assert_eq!(r.optional, OPTIONAL_COLUMNS);
+                let back: Vec<String> = r.map(|l| l.unwrap().to_string()).collect();
+                let links: Vec<String> = links.iter().map(|l| l.to_string()).collect();
+                assert_eq!(back, links, "{} ipv6={}", format, ipv6);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn delays_are_typed() {
+        let dir = scratch("delays");
+        let path = dir.join("links.arrow");
+        write(&path, LinkFormat::Arrow, false, &[link("10.0.0.1", "10.0.0.2", "192.0.2.1")]);
+        let schema = FileReader::try_new(File::open(&path).unwrap(), None).unwrap().schema();
+        match schema.field_with_name(DELAYS_COLUMN).unwrap().data_type() {
+            DataType::Struct(fields) => {
+                let names: Vec<&str> = fields.iter().map(|f| f.name().as_str()).collect();
+                assert_eq!(names, ["min", "max", "zeros", "bins"]);
+                assert!(matches!(fields[3].data_type(), DataType::Map(..)), "{:?}", fields[3]);
+            }
+            t => panic!("delays is {}", t),
+        }
+        // an empty sketch is rejected as in the text form
+        let mut l = link("10.0.0.1", "10.0.0.2", "192.0.2.1");
+        l.prop.delays = Some(DelaySketch { min: 0.0, max: 0.0, zeros: 0, bins: BTreeMap::new() });
+        write(&path, LinkFormat::Arrow, false, &[l]);
+        let e = read_columnar(&path).next().unwrap().unwrap_err();
+        assert!(e.starts_with("empty delay sketch"), "{}", e);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ipv6_after_an_ipv4_batch_is_an_error() {
+        let dir = scratch("late-ipv6");
+        let path = dir.join("links.parquet");
+        let mut w = create(&path, LinkFormat::Parquet, Codec::Plain, Some(&header())).unwrap();
+        for i in 0..BATCH {
+            w.write(&link(&format!("10.{}.{}.1", i / 256 % 256, i % 256), "10.0.0.2", "192.0.2.1")).unwrap();
+        }
+        w.write(&link("2001:db8::1", "2001:db8::2", "2001:db8::ff")).unwrap();
+        assert!(w.finish().is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_typed_file_has_header_columns() {
+        let dir = scratch("empty");
+        for format in [LinkFormat::Parquet, LinkFormat::Arrow] {
+            let path = dir.join(format!("empty{}", format.suffix()));
+            write(&path, format, false, &[]);
+            let mut r = read_columnar(&path);
+            assert_eq!(r.optional, OPTIONAL_COLUMNS);
+            assert!(r.next().is_none());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let dir = scratch("text");
+        let path = dir.join("links.csv.gz");
+        let links = [
+            link("10.0.0.1", "10.0.0.2", "192.0.2.1"),
+            link("2001:db8::1", "2001:db8::2", "2001:db8::ff"),
+        ];
+        let mut w = create(&path, LinkFormat::Csv, Codec::Gzip, Some(&header())).unwrap();
+        for l in &links {
+            w.write(l).unwrap();
+        }
+        w.finish().unwrap();
+        let mut r = match open(&path).unwrap() {
+            LinkSource::Text(r) => r,
+            LinkSource::Columnar(_) => panic!("gzip csv read as columnar"),
+        };
+        let h = read_header(&mut r).unwrap().unwrap();
+        assert_eq!(h, header());
+        assert_eq!(h.version, LINK_SCHEMA_VERSION);
+        for (line, l) in r.lines().zip(&links) {
+            let back = Link::parse_columns(&line.unwrap(), &h.columns).unwrap();
+            assert_eq!(back.to_string(), l.to_string());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn jsonl_keys() {
+        let dir = scratch("jsonl");
+        let path = dir.join("links.jsonl");
+        let mut l = link("10.0.0.1", "10.0.0.2", "192.0.2.1");
+        l.prop.delays = None;
+        l.prop.rtt = None;
+        let mut w = create(&path, LinkFormat::Jsonl, Codec::Plain, None).unwrap();
+        w.write(&l).unwrap();
+        w.finish().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let v: serde_json::Value = serde_json::from_str(text.trim_end()).unwrap();
+        let keys: Vec<&str> = v.as_object().unwrap().keys().map(|k| k.as_str()).collect();
+        let mut expected: Vec<&str> = LINK_COLUMNS.to_vec();
+        expected.extend([MONITORS_COLUMN, LOOPS_COLUMN, MPLS_COLUMN]);
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(v["in"], "10.0.0.1");
+        assert_eq!(v["monitors"]["192.0.2.1"], 2);
+        // output only
+        let e = open(&path).err().unwrap();
+        assert!(e.to_string().starts_with("jsonl links can't be read back"), "{}", e);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn typed_formats_are_not_compressed() {
+        let dir = scratch("compressed");
+        let r = create(&dir.join("links.parquet.gz"), LinkFormat::Parquet, Codec::Gzip, None);
+        assert!(r.is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,129 +1,722 @@
 // linkmerge -- merge a batch of link files
 // =============================================================================
-// USAGE: linkmerge file1 file2 ...
-// INPUT: a batch of link (text or gzip) file names from STDIN or @ARGV
+// USAGE: see Usage below (./linkmerge -h)
+// INPUT: a batch of link file names from STDIN or @ARGV, plain or
+//        gzip/zstd/xz/bz2 compressed, detected by content
+//        each file sorted by (in, out), as written by trace2link,
+//        or in any order with --sort
+//        malformed lines are handled according to --on-error, default fail,
+//        a file with another number of columns, e.g. from an older trace2link, is an error
+//        a #links header line, see link::LinkHeader, is checked for the schema version
+//        and columns, a file without it is read as the legacy headerless format
+//        parquet and arrow files of trace2link --format are detected by content too,
+//        jsonl ones are an error, the format is output only
+// OUTPUT: a #links header with the monitors and the measurement window of all
+//         input headers, unless --no-header, then the merged links
+//         --format jsonl|parquet|arrow writes JSON lines or typed columns instead, see linkio,
+//         the text inputs are read through once more for the IP family of the typed columns
+// NOTE:  the files are merged with a k-way heap merge, one line (or one record
+//        batch of a parquet/arrow file) per file in memory;
+//        --sort first cuts each file into sorted runs of at most --memory MB in
+//        a temporary directory, which are then merged like sorted inputs, in
+//        passes of at most FAN_IN runs at once; the runs are removed at the end,
+//        after an error too
 
-// INPUT/OUTPUT format: CSV text
-//         1.in 2.out 3.is_dest 4.star 5.delay 6.freq 7.ttl 8.monitor
+// INPUT/OUTPUT format: CSV text, see link::Link
+//         1.in 2.out 3.is_dest 4.star 5.delay 6.freq 7.ttl 8.monitor 9.firstseen 10.lastseen
 //         1. the IP address of the ingress interface, e.g., 1.2.3.4
 //         2. the IP address of the outgress interface, e.g., 5.6.7.8
 //         3. whether the outgress node is the destination, e.g., Y or N
 //         4. the number of anonymous (*) hops inbetween, e.g., 0 for directed link
-//         5. the minimal delay in ms > 0, e.g., 10
+//         5. the delay in ms >= 0, the minimal one or as estimated by --delay, e.g., 10
 //         6. the cumulative frequence of link observed, e.g., 5000
 //         7. the minimal TTL of the ingress interface, e.g., 7
 //         8. the monoitor which observed the link at the minimal TTL, e.g., 9.0.1.2
+//         9. the earliest start time of the traces observing the link, e.g., 1677196800
+//         10. the latest start time of the traces observing the link, e.g., 1677283200
+//         11. optional, the observations per monitor of trace2link -M, e.g., 9.0.1.2=3,9.0.1.3=2
+//             summed per monitor with -M, dropped otherwise
+//         12. optional, the looping observations of trace2link --loops=flag, e.g., 1
+//             summed, and kept if any input header has the loops column
+//         then optional, the delay sketch of trace2link --delay, merged, and kept if all
+//         inputs have it, the delay of a link with a sketch is re-estimated from it
+//         and optional, the minimal RTT to in and out of trace2link --rtt, kept if all inputs have it
+//         and optional, the observations in an MPLS tunnel of trace2link --mpls, summed,
+//         kept if all inputs have it
+// the loop counters of the input headers are summed into the output header,
+// or left out if an input has none, e.g. a legacy file
 
-use std::collections::{HashMap, VecDeque};
-use std::io::{BufRead, BufReader, Read};
-use std::path::PathBuf;
+use hitscanner::compress::Codec;
+use hitscanner::error::{read_line, Format, InputError, LineError, OnError, Rejects};
+use hitscanner::link::{
+    read_header, LinkHeader, LoopStats, DELAYS_COLUMN, LEGACY_FIELDS, LOOPS_COLUMN, MONITORS_COLUMN,
+    MPLS_COLUMN, RTT_IN_COLUMN, RTT_OUT_COLUMN,
+};
+use hitscanner::linkio::{self, LinkFormat, LinkSource};
+use hitscanner::sketch::DelayEstimator;
+use hitscanner::{InOut, Link, LinkProp};
 
-const NLINE: u32 = 100000;
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const HELP: &str = "\
+Usage: linkmerge [OPTIONS] file1 file2 ...
+
+OPTIONS:
+-h   print this help message
+-s   sort the inputs first, for files not sorted by (in, out)
+-M   keep the observations per monitor, all inputs must have them (trace2link -M)
+-m   the memory budget of a sorted run in MB, default 1024
+-T   the directory of the sorted runs, default $TMPDIR
+--delay       min|median|pNN estimator of the link delay, default that of the inputs,
+              other than min needs the delay sketches of trace2link --delay in all inputs
+--compress    none|gzip|zstd|xz|bz2 compression of the output, default none
+--format      csv|jsonl|parquet|arrow output format, default csv,
+              parquet and arrow can't be used with --compress,
+              jsonl is for other tools, linkmerge can't read it back
+--no-header   write the legacy format without the #links header line
+--on-error    skip|fail|quarantine malformed lines, default fail
+--quarantine  file of the quarantined lines, default linkmerge.rejected
+";
+
+// estimated memory of a link held in a sorted run, on top of its strings
+const LINK_OVERHEAD: usize = 128;
+
+// the most sorted runs merged at once, well within the open file limit,
+// more are first merged into bigger runs
+const FAN_IN: usize = 64;
+
+struct AppArgs {
+    sort: bool,
+    monitors: bool,
+    delay: Option<DelayEstimator>,
+    memory: usize,
+    tmpdir: PathBuf,
+    on_error: OnError,
+    quarantine: PathBuf,
+    codec: Codec,
+    format: LinkFormat,
+    header: bool,
+    inputs: Vec<std::ffi::OsString>,
+}
+
+fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
+    Ok(s.into())
+}
+
+fn getoption() -> Result<AppArgs, pico_args::Error> {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    // Help has a higher priority and should be handled separately.
+    if pargs.contains(["-h", "--help"]) {
+        print!("{}", HELP);
+        std::process::exit(0);
+    }
+
+    Ok(AppArgs {
+        sort: pargs.contains(["-s", "--sort"]),
+        monitors: pargs.contains(["-M", "--monitors"]),
+        delay: pargs.opt_value_from_str("--delay")?,
+        memory: pargs.opt_value_from_str(["-m", "--memory"])?.unwrap_or(1024),
+        tmpdir: pargs
+            .opt_value_from_os_str(["-T", "--tmpdir"], parse_path)?
+            .unwrap_or(std::env::temp_dir()),
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("linkmerge.rejected")),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
+        format: pargs.opt_value_from_str("--format")?.unwrap_or(LinkFormat::Csv),
+        header: !pargs.contains("--no-header"),
+        inputs: pargs.finish(),
+    })
+}
 
 // I/O helpers
-fn openfile(path: &std::path::PathBuf) -> BufReader<Box<dyn Read>> {
-    let input: Box<dyn std::io::Read + 'static> = if path.as_os_str() == "-" {
-        Box::new(std::io::stdin())
-    } else {
-        Box::new(std::fs::File::open(&path).unwrap())
-    };
+fn openfile(path: &Path) -> Result<LinkSource, String> {
+    linkio::open(path).map_err(|e| format!("{}: {}", path.display(), e))
+}
 
-    let reader: BufReader<Box<dyn Read>> = BufReader::new(input);
+// a rejected line, the merge stops with --on-error fail or if it can't be quarantined
+fn reject(rejects: &mut Rejects, e: &InputError) -> Result<(), String> {
+    rejects.reject(e).map_err(|e| e.to_string())
+}
 
-    return reader;
+// the optional columns kept in the output
+#[derive(Clone, Copy, Default)]
+struct Keep {
+    monitors: bool, // the per-monitor counts, see -M
+    loops: bool,    // the loops column, 0 for an input without it
+    delays: bool,   // the delay sketches, all inputs have them
+    rtt: bool,      // the RTT columns, all inputs have them
+    mpls: bool,     // the MPLS column, all inputs have it
 }
 
-fn readfile(
-    f: usize,
-    file: &mut BufReader<Box<dyn std::io::Read>>,
-    current_lines: &mut VecDeque<String>,
-    lines_left: &mut Vec<u32>,
-) -> u32 {
-    let mut i = 0;
-    while i < NLINE {
-        let mut buf = String::new();
-        if file.read_line(&mut buf).unwrap() == 0 {
-            break;
+// an open link file and where we are in it
+struct Input {
+    path: PathBuf,
+    name: String,
+    source: LinkSource,
+    header: Option<LinkHeader>, // None for a legacy file
+    lineno: usize, // the row of a parquet/arrow file
+    checked: bool, // the columns of the first link are checked
+    keep: Keep,
+}
+
+impl Input {
+    fn open(path: &Path, keep: Keep) -> Result<Self, String> {
+        let name = path.display().to_string();
+        let mut source = openfile(path)?;
+        let header = match &mut source {
+            LinkSource::Text(file) => read_header(file).map_err(|e| format!("{}:1: {}", name, e))?,
+            LinkSource::Columnar(reader) => reader.header.clone(),
+        };
+        Ok(Input {
+            path: path.to_path_buf(),
+            name,
+            source,
+            lineno: header.is_some() as usize,
+            header,
+            checked: false,
+            keep,
+        })
+    }
+
+    fn has_column(&self, column: &str) -> bool {
+        match &self.source {
+            LinkSource::Columnar(reader) => reader.optional.iter().any(|c| c == column),
+            LinkSource::Text(_) => self.header.as_ref().is_some_and(|h| h.has_column(column)),
+        }
+    }
+
+    // whether the file has an IPv6 link, for the address columns of the typed formats;
+    // a text file is read through once more for it, STDIN can't be and is taken as IPv6
+    fn has_ipv6(&self) -> Result<bool, String> {
+        match &self.source {
+            LinkSource::Columnar(reader) => Ok(reader.ipv6),
+            LinkSource::Text(_) if self.path.as_os_str() == "-" => Ok(true),
+            LinkSource::Text(_) => {
+                let mut file = match openfile(&self.path)? {
+                    LinkSource::Text(file) => file,
+                    LinkSource::Columnar(reader) => return Ok(reader.ipv6),
+                };
+                // only an IPv6 address has a colon, but the monitors of the header line
+                while let Some(line) = read_line(&mut file) {
+                    match line {
+                        Ok(l) if !l.starts_with('#') && l.contains(':') => return Ok(true),
+                        Err(LineError::Io(_)) => break, // the merge rejects it
+                        _ => {}
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    // the delay estimator of the delay column, min for a legacy file
+    fn delay(&self) -> DelayEstimator {
+        self.header.as_ref().map_or(DelayEstimator::Min, |h| h.delay)
+    }
+
+    // the next valid link, with the optional columns of `keep`
+    fn readlink(&mut self, rejects: &mut Rejects) -> Result<Option<Link>, String> {
+        let Some(mut link) = self.nextlink(rejects)? else {
+            return Ok(None);
+        };
+        link.prop.loops = if self.keep.loops {
+            Some(link.prop.loops.unwrap_or(0))
+        } else {
+            None
+        };
+        // the delay column may be another estimate, the min of a sketch merges exactly
+        if let Some(d) = &link.prop.delays {
+            link.prop.delay = d.min;
+        }
+        if !self.keep.delays {
+            link.prop.delays = None;
+        }
+        if !self.keep.rtt {
+            link.prop.rtt = None;
+        }
+        if !self.keep.mpls {
+            link.prop.mpls = None;
         }
-        let l = format!("{} {}", buf, f);
-        current_lines.push_back(l);
-        i += 1;
+        if !self.keep.monitors {
+            link.prop.monitors = None;
+        } else if link.prop.monitors.is_none() {
+            return Err(format!(
+                "{}:{}: no observations per monitor for -M, write the input with trace2link -M",
+                self.name, self.lineno
+            ));
+        }
+        Ok(Some(link))
     }
 
-    lines_left[f] = i;
-    return i;
+    // the next link as it is in the input, malformed lines are rejected
+    fn nextlink(&mut self, rejects: &mut Rejects) -> Result<Option<Link>, String> {
+        let file = match &mut self.source {
+            LinkSource::Text(file) => file,
+            LinkSource::Columnar(reader) => loop {
+                self.lineno += 1;
+                match reader.next() {
+                    None => return Ok(None),
+                    Some(Ok(link)) => return Ok(Some(link)),
+                    Some(Err(e)) => reject(rejects, &InputError::new(Format::Link, self.lineno, &e).in_file(&self.name))?,
+                }
+            },
+        };
+        loop {
+            let Some(r) = read_line(file) else {
+                return Ok(None);
+            };
+            self.lineno += 1;
+            let buf = match r {
+                Ok(l) => l,
+                Err(e) => {
+                    reject(rejects, &e.reject(Format::Link, self.lineno, &self.name))?;
+                    match e {
+                        LineError::Utf8(_) => continue,
+                        LineError::Io(_) => return Ok(None),
+                    }
+                }
+            };
+            let line = buf.trim_end();
+            let columns = line.split_whitespace().count();
+            // the columns of the header, or of a legacy file with or without the monitors and loops columns,
+            // checked on the first line
+            let expected = match &self.header {
+                _ if self.checked => None,
+                Some(h) if columns != h.columns.len() => {
+                    Some(format!("{} ({})", h.columns.len(), h.columns.join(" ")))
+                }
+                None if !LEGACY_FIELDS.contains(&columns) => Some(format!(
+                    "{} to {} (in out is_dest star delay freq ttl monitor firstseen lastseen [monitors] [loops])",
+                    LEGACY_FIELDS.start(),
+                    LEGACY_FIELDS.end()
+                )),
+                _ => None,
+            };
+            if let Some(expected) = expected {
+                // not a bad line but another format, nothing in the file would merge
+                return Err(format!(
+                    "{}: schema mismatch, {} columns instead of {}",
+                    self.name, columns, expected
+                ));
+            }
+            self.checked = true;
+            let link = match &self.header {
+                Some(h) => Link::parse_columns(line, &h.columns),
+                None => line.parse::<Link>(),
+            };
+            match link {
+                Ok(link) => return Ok(Some(link)),
+                Err(e) => reject(
+                    rejects,
+                    &InputError::new(Format::Link, self.lineno, &e)
+                        .in_file(&self.name)
+                        .with_raw(line),
+                )?,
+            }
+        }
+    }
 }
 
-fn main() {
-    let pargs = pico_args::Arguments::from_env();
-    let mut inputs = pargs.finish();
+// merges consecutive observations of the same link with LinkProp::merge,
+// `write` gets one link per (in, out)
+struct Merger<W: FnMut(&Link) -> Result<(), String>> {
+    current: Option<Link>,
+    write: W,
+}
 
-    // remove duplicate filenames
-    let mut h = HashMap::new();
-    inputs.retain(|e| h.insert(String::from(e.to_str().unwrap()), true).is_none());
+impl<W: FnMut(&Link) -> Result<(), String>> Merger<W> {
+    fn new(write: W) -> Self {
+        Merger { current: None, write }
+    }
 
-    // open all files
-    let mut files: Vec<_> = inputs
-        .iter()
-        .map(|e| openfile(&PathBuf::from(&e)))
-        .collect();
+    fn push(&mut self, link: Link) -> Result<(), String> {
+        match self.current.as_mut() {
+            Some(b) if b.io == link.io => b.prop.merge(&link.prop),
+            _ => {
+                self.flush()?;
+                self.current = Some(link);
+            }
+        }
+        Ok(())
+    }
 
-    let mut lines_left: Vec<u32> = inputs.iter().map(|_| 0).collect();
-    let mut current_lines: VecDeque<String> = VecDeque::new();
+    fn flush(&mut self) -> Result<(), String> {
+        match self.current.take() {
+            Some(b) => (self.write)(&b),
+            None => Ok(()),
+        }
+    }
+}
 
-    for (i, file) in files.iter_mut().enumerate() {
-        readfile(i as usize, file, &mut current_lines, &mut lines_left);
+// The sorted runs in the temporary directory, removed when dropped,
+// whether the merge went through or stopped with an error.
+struct Runs {
+    tmpdir: PathBuf,
+    paths: Vec<PathBuf>, // in input order
+    created: usize,
+}
+
+impl Runs {
+    fn new(tmpdir: &Path) -> Self {
+        Runs {
+            tmpdir: tmpdir.to_path_buf(),
+            paths: Vec::new(),
+            created: 0,
+        }
     }
-    current_lines.make_contiguous().sort();
 
-    while current_lines.len() > 0 {
-        let al = current_lines.pop_front().unwrap();
-        let mut a: Vec<String> = al.split_whitespace().map(|x| x.to_string()).collect();
-        let af = a.pop().unwrap().parse::<usize>().unwrap();
-        lines_left[af] -= 1;
+    // a new run after the others, the header tells the optional columns apart
+    // when it is read back
+    fn create(&mut self, header: &LinkHeader) -> Result<Run, String> {
+        let path = self.tmpdir.join(format!("linkmerge.{}.{}.run", std::process::id(), self.created));
+        self.created += 1;
+        let file = File::create(&path).map_err(|e| format!("can't create {}: {}", path.display(), e))?;
+        self.paths.push(path.clone());
+        let mut run = Run { path, w: BufWriter::new(file) };
+        writeln!(run.w, "{}", header).map_err(|e| run.error(e))?;
+        Ok(run)
+    }
+}
 
-        if current_lines.len() <= 0 {
-            println!("{}", a.join(" "));
-            for line in BufReader::new(files[af].by_ref()).lines() {
-                println!("{}", line.unwrap());
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for run in &self.paths {
+            let _ = std::fs::remove_file(run);
+        }
+    }
+}
+
+// a run being written, see Runs::create
+struct Run {
+    path: PathBuf,
+    w: BufWriter<File>,
+}
+
+impl Run {
+    fn error(&self, e: io::Error) -> String {
+        format!("can't write {}: {}", self.path.display(), e)
+    }
+
+    fn write(&mut self, link: &Link) -> Result<(), String> {
+        writeln!(self.w, "{}", link).map_err(|e| self.error(e))
+    }
+
+    fn finish(mut self) -> Result<PathBuf, String> {
+        self.w.flush().map_err(|e| self.error(e))?;
+        Ok(self.path)
+    }
+}
+
+// The external-sort pre-pass: cut an input into runs of at most `budget` bytes,
+// each sorted by (in, out) with its equal links already merged.
+fn sort_runs(
+    input: &mut Input,
+    header: &LinkHeader,
+    budget: usize,
+    runs: &mut Runs,
+    rejects: &mut Rejects,
+) -> Result<(), String> {
+    let mut eof = false;
+    while !eof {
+        let mut links: Vec<Link> = Vec::new();
+        let mut size = 0;
+        while size < budget {
+            match input.readlink(rejects)? {
+                Some(link) => {
+                    size += link.io._in.len() + link.io.out.len() + link.prop.monitor.len() + LINK_OVERHEAD;
+                    links.push(link);
+                }
+                None => {
+                    eof = true;
+                    break;
+                }
             }
+        }
+        if links.is_empty() {
             break;
         }
+        // stable, so equal links keep their input order
+        links.sort_by(|a, b| a.io.cmp(&b.io));
+
+        let mut run = runs.create(header)?;
+        let mut merger = Merger::new(|l: &Link| run.write(l));
+        for link in links {
+            merger.push(link)?;
+        }
+        merger.flush()?;
+        drop(merger);
+        run.finish()?;
+    }
+    Ok(())
+}
 
-        let bl = &current_lines[0];
-        let mut b: Vec<String> = bl.split_whitespace().map(|x| x.to_string()).collect();
-        let bf = b.pop().unwrap().parse::<usize>().unwrap();
+// The k-way merge: a heap of the next link of each input, keyed on (in, out)
+// and then the input index, so equal links leave the heap in input order.
+// Every input must be sorted, the merge stops with an error otherwise.
+fn merge(
+    inputs: &mut [Input],
+    rejects: &mut Rejects,
+    write: impl FnMut(&Link) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut heap: BinaryHeap<Reverse<(InOut, usize)>> = BinaryHeap::new();
+    let mut heads: Vec<Option<LinkProp>> = Vec::new();
+    for (f, input) in inputs.iter_mut().enumerate() {
+        heads.push(input.readlink(rejects)?.map(|link| {
+            heap.push(Reverse((link.io, f)));
+            link.prop
+        }));
+    }
 
-        if a[0] != b[0] || a[1] != b[1] {
-            println!("{}", a.join(" "));
-        } else {
-            if a[2] == "N" {
-                b[2] = "N".to_string();
-            }
-            if b[3].parse::<u32>().unwrap() > a[3].parse::<u32>().unwrap() {
-                b[3] = a[3].to_string();
+    let mut merger = Merger::new(write);
+    while let Some(Reverse((io, f))) = heap.pop() {
+        let prop = heads[f].take().unwrap();
+        if let Some(next) = inputs[f].readlink(rejects)? {
+            if next.io < io {
+                return Err(format!(
+                    "{}:{}: input is not sorted by (in, out), use --sort",
+                    inputs[f].name, inputs[f].lineno
+                ));
             }
-            if b[4].parse::<f64>().unwrap() > a[4].parse::<f64>().unwrap() {
-                b[4] = a[4].to_string();
+            heap.push(Reverse((next.io, f)));
+            heads[f] = Some(next.prop);
+        }
+        merger.push(Link { io, prop })?;
+    }
+    merger.flush()
+}
+
+// Merge passes over the runs, each merging consecutive groups of at most
+// `fan_in` runs into one, until the inputs of the final merge are at most
+// `fan_in`. A group keeps the order of its runs, and the groups theirs, so
+// equal links are merged in input order as in a single merge.
+fn merge_runs(
+    runs: &mut Runs,
+    header: &LinkHeader,
+    keep: Keep,
+    fan_in: usize,
+    rejects: &mut Rejects,
+) -> Result<Vec<Input>, String> {
+    while runs.paths.len() > fan_in {
+        let pass = runs.paths.clone();
+        let mut merged = Vec::new();
+        for group in pass.chunks(fan_in) {
+            if group.len() == 1 {
+                merged.push(group[0].clone());
+                continue;
             }
-            b[5] = (b[5].parse::<u32>().unwrap() + a[5].parse::<u32>().unwrap()).to_string();
-            let b6 = b[6].parse::<u32>().unwrap();
-            let a6 = a[6].parse::<u32>().unwrap();
-            if b6 > a6 || (b6 == a6 && a[7] < b[7]) {
-                b[6] = a[6].to_string();
-                b[7] = a[7].to_string();
+            let mut inputs = group.iter().map(|run| Input::open(run, keep)).collect::<Result<Vec<_>, _>>()?;
+            let mut run = runs.create(header)?;
+            merge(&mut inputs, rejects, |l: &Link| run.write(l))?;
+            merged.push(run.finish()?);
+            for run in group {
+                let _ = std::fs::remove_file(run);
             }
+        }
+        runs.paths = merged;
+    }
+    runs.paths.iter().map(|run| Input::open(run, keep)).collect()
+}
 
-            b.push(String::from(bf.to_string()));
-            current_lines[0] = b.join(" ");
+// The whole merge into `out`, with --sort, `budget` and `tmpdir`, through
+// sorted runs that are removed when it returns.
+fn merge_all(
+    mut files: Vec<Input>,
+    sort: Option<(usize, &Path)>,
+    header: &LinkHeader,
+    keep: Keep,
+    delay: DelayEstimator,
+    rejects: &mut Rejects,
+    out: &mut linkio::LinkWriter,
+) -> Result<(), String> {
+    let mut runs = None;
+    if let Some((budget, tmpdir)) = sort {
+        let runs = runs.insert(Runs::new(tmpdir));
+        for input in files.iter_mut() {
+            sort_runs(input, header, budget, runs, rejects)?;
         }
+        files = merge_runs(runs, header, keep, FAN_IN, rejects)?;
+    }
+    merge(&mut files, rejects, |l: &Link| {
+        let mut l = l.clone();
+        l.prop.estimate_delay(delay);
+        out.write(&l).map_err(|e| format!("can't write -: {}", e))
+    })
+}
 
-        if lines_left[af] == 0
-        {
-            let lines_read = readfile(af, &mut files[af], &mut current_lines, &mut lines_left);
-            if lines_read != 0 {
-                current_lines.make_contiguous().sort();
+fn main() {
+    let args = match getoption() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    let mut inputs = args.inputs;
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine).unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    if args.format.is_columnar() && args.codec != Codec::Plain {
+        eprintln!("Error: --format {} can't be used with --compress.", args.format);
+        std::process::exit(1);
+    }
+
+    // remove duplicate filenames
+    let mut h = HashMap::new();
+    inputs.retain(|e| h.insert(e.clone(), true).is_none());
+
+    // open all files
+    let files: Result<Vec<Input>, String> = inputs
+        .iter()
+        .map(|e| Input::open(&PathBuf::from(e), Keep::default()))
+        .collect();
+    let mut files = files.unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    let keep = Keep {
+        monitors: args.monitors,
+        loops: files.iter().any(|f| f.has_column(LOOPS_COLUMN)),
+        delays: !files.is_empty() && files.iter().all(|f| f.has_column(DELAYS_COLUMN)),
+        rtt: !files.is_empty() && files.iter().all(|f| f.has_column(RTT_IN_COLUMN)),
+        mpls: !files.is_empty() && files.iter().all(|f| f.has_column(MPLS_COLUMN)),
+    };
+    files.iter_mut().for_each(|f| f.keep = keep);
+    let delay = match args.delay {
+        Some(d) => d,
+        None => match files.iter().map(|f| f.delay().to_string()).dedup().count() {
+            0 => DelayEstimator::Min,
+            1 => files[0].delay(),
+            _ => {
+                eprintln!("Error: the inputs have different delay estimators, choose one with --delay.");
+                std::process::exit(1);
             }
+        },
+    };
+    if delay != DelayEstimator::Min {
+        if let Some(f) = files.iter().find(|f| !f.has_column(DELAYS_COLUMN)) {
+            eprintln!(
+                "Error: {}: no delay sketches for --delay {}, write the input with trace2link --delay.",
+                f.name, delay
+            );
+            std::process::exit(1);
         }
     }
+    let mut header = LinkHeader::new(concat!("linkmerge/", env!("CARGO_PKG_VERSION")))
+        .with_column(MONITORS_COLUMN, keep.monitors)
+        .with_column(LOOPS_COLUMN, keep.loops)
+        .with_column(DELAYS_COLUMN, keep.delays)
+        .with_column(RTT_IN_COLUMN, keep.rtt)
+        .with_column(RTT_OUT_COLUMN, keep.rtt)
+        .with_column(MPLS_COLUMN, keep.mpls);
+    header.delay = delay;
+    // the loop counters, as long as every input has them
+    if let Some(l) = files.first().and_then(|f| f.header.as_ref()?.loops.as_ref()) {
+        header.loops = Some(LoopStats { traces: 0, looping: 0, links: 0, ..l.clone() });
+    }
+    for f in &files {
+        match &f.header {
+            Some(h) => header.merge(h),
+            None => header.loops = None,
+        }
+    }
+    // IPv4 links get UInt32 columns unless an input has IPv6 ones, anywhere in it
+    let mut ipv6 = false;
+    if args.format.is_columnar() {
+        for f in &files {
+            match f.has_ipv6() {
+                Ok(false) => {}
+                Ok(true) => {
+                    ipv6 = true;
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Error: {}.", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    let path = PathBuf::from("-");
+    let mut out = match linkio::create(&path, args.format, args.codec, Some(&header).filter(|_| args.header)) {
+        Ok(w) => w.ipv6(ipv6).columns(&header.columns),
+        Err(e) => {
+            eprintln!("Error: can't write {}: {}.", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    let sort = args.sort.then(|| (args.memory.max(1) << 20, args.tmpdir.as_path()));
+    let merged = merge_all(files, sort, &header, keep, delay, &mut rejects, &mut out)
+        .and_then(|_| out.finish().map_err(|e| format!("can't write -: {}", e)));
+    if let Err(e) = merged {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = rejects.summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // links 10.0.0.<n> -> 10.0.0.9, some of them observed twice
+    const LINKS: &str = "\
+10.0.0.5 10.0.0.9 N 0 0.500 1 3 192.0.2.1 1677196800 1677196800
+10.0.0.1 10.0.0.9 N 0 0.700 2 4 192.0.2.1 1677196800 1677196800
+10.0.0.3 10.0.0.9 N 0 0.400 1 2 192.0.2.2 1677196900 1677196900
+10.0.0.5 10.0.0.9 N 0 0.200 1 3 192.0.2.2 1677196900 1677196900
+10.0.0.2 10.0.0.9 Y 0 0.300 1 5 192.0.2.1 1677196800 1677196800
+10.0.0.1 10.0.0.9 N 0 0.100 1 4 192.0.2.2 1677196900 1677196900
+10.0.0.4 10.0.0.9 N 0 0.600 1 1 192.0.2.1 1677196800 1677196800
+";
+
+    // the merged links of LINKS, a run per link, at most `fan_in` runs merged at once,
+    // and the runs left in the directory before and after the merge
+    fn merge_sorted(test: &str, fan_in: usize) -> (Vec<String>, usize, usize) {
+        let dir = std::env::temp_dir().join(format!("hitscanner-linkmerge-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("links"), LINKS).unwrap();
+        let header = LinkHeader::new("linkmerge/test");
+        let mut rejects = Rejects::new(OnError::Fail, Path::new("unused")).unwrap();
+        let mut runs = Runs::new(&dir);
+        let mut input = Input::open(&dir.join("links"), Keep::default()).unwrap();
+        sort_runs(&mut input, &header, 1, &mut runs, &mut rejects).unwrap();
+        let mut inputs = merge_runs(&mut runs, &header, Keep::default(), fan_in, &mut rejects).unwrap();
+        let before = std::fs::read_dir(&dir).unwrap().count() - 1;
+        let mut links = Vec::new();
+        merge(&mut inputs, &mut rejects, |l: &Link| {
+            links.push(l.to_string());
+            Ok(())
+        })
+        .unwrap();
+        drop(runs);
+        let after = std::fs::read_dir(&dir).unwrap().count() - 1;
+        std::fs::remove_dir_all(&dir).unwrap();
+        (links, before, after)
+    }
+
+    #[test]
+    fn merge_passes_keep_the_single_merge() {
+        let (single, runs, left) = merge_sorted("single", FAN_IN);
+        assert_eq!((runs, left), (7, 0));
+        assert_eq!(single.len(), 5);
+        assert!(single[0].starts_with("10.0.0.1 10.0.0.9 N 0 0.100 3 4 192.0.2.1 1677196800 1677196900"), "{:?}", single);
+        // 7 runs, then 4, then 2
+        let (passes, runs, left) = merge_sorted("passes", 2);
+        assert_eq!((runs, left), (2, 0));
+        assert_eq!(passes, single);
+    }
 }
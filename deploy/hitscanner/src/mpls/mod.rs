@@ -0,0 +1,103 @@
+// MPLS tunnels seen in traceroute replies, see trace2link --hops and --mpls
+//   - Tunnel: the signature of a tunnel at a hop
+//     - explicit: the reply has an MPLS label stack extension (RFC 4950)
+//     - implicit: no labels, but a quoted TTL above 1, the LSE TTL was
+//       copied into the IP TTL by the ingress LSR, only in warts
+//   - HopNote: the tunnel observations of an interface, merged over all traces
+//     one line of a hop annotation file: addr tunnel freq qttl labels, e.g.
+//     10.0.0.5 explicit 3 1 16005,16006
+//     with the largest quoted TTL, the distinct top labels or - for none
+//   - annotate, mergenotes: add the hops of a trace, or another note map
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::trace::{Hop, Trace};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tunnel {
+    Implicit,
+    Explicit, // wins over implicit when merged
+}
+
+impl fmt::Display for Tunnel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Tunnel::Implicit => write!(f, "implicit"),
+            Tunnel::Explicit => write!(f, "explicit"),
+        }
+    }
+}
+
+// the tunnel signature of a reply, if any
+pub fn tunnel(hop: &Hop) -> Option<Tunnel> {
+    if !hop.mpls.is_empty() {
+        Some(Tunnel::Explicit)
+    } else if hop.quoted_ttl > 1 {
+        Some(Tunnel::Implicit)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HopNote {
+    pub tunnel: Tunnel,
+    pub freq: u32,
+    pub qttl: u8,
+    pub labels: BTreeSet<u32>,
+}
+
+impl HopNote {
+    pub fn merge(&mut self, other: &HopNote) {
+        self.tunnel = self.tunnel.max(other.tunnel);
+        self.freq += other.freq;
+        self.qttl = self.qttl.max(other.qttl);
+        self.labels.extend(other.labels.iter());
+    }
+}
+
+impl fmt::Display for HopNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} ", self.tunnel, self.freq, self.qttl)?;
+        if self.labels.is_empty() {
+            return write!(f, "-");
+        }
+        let labels: Vec<String> = self.labels.iter().map(|l| l.to_string()).collect();
+        write!(f, "{}", labels.join(","))
+    }
+}
+
+// the replies of a trace with a tunnel signature
+pub fn annotate(trace: &Trace, notes: &mut HashMap<String, HopNote>) {
+    for hop in &trace.hops {
+        let tunnel = match tunnel(hop) {
+            Some(t) => t,
+            None => continue,
+        };
+        let note = HopNote {
+            tunnel,
+            freq: 1,
+            qttl: hop.quoted_ttl,
+            labels: hop.mpls.first().map(|m| m.label).into_iter().collect(),
+        };
+        match notes.get_mut(&hop.addr.to_string()) {
+            Some(n) => n.merge(&note),
+            None => {
+                notes.insert(hop.addr.to_string(), note);
+            }
+        }
+    }
+}
+
+// merge a note map, e.g. built by another thread, into `notes`
+pub fn mergenotes(other: HashMap<String, HopNote>, notes: &mut HashMap<String, HopNote>) {
+    for (addr, note) in other {
+        match notes.get_mut(&addr) {
+            Some(n) => n.merge(&note),
+            None => {
+                notes.insert(addr, note);
+            }
+        }
+    }
+}
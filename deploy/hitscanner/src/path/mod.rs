@@ -0,0 +1,239 @@
+// IP-level paths, one per trace, as written by trace2path
+//   - TracePath: the monitor, destination, start time, stop reason and the
+//     ordered hops of a trace, with a star for each TTL without a reply
+//   - PathHop: a reply, or a star, at a TTL
+//   - JSONL: one TracePath per line, e.g.
+//     {"monitor":"192.0.2.1","dst":"10.0.0.9","start":1677196800,"stop":"completed",
+//      "hops":[{"ttl":1,"addr":"10.0.0.1","rtt":1.0},{"ttl":2,"star":true},...]}
+//   - binary: PATH_MAGIC, then per path, all integers big-endian as in warts
+//     uint8 family (4 or 6), monitor and destination address (4 or 16 bytes each),
+//     uint32 start, uint8 stop reason (trace::StopReason), uint16 hop count,
+//     then per hop: uint8 ttl, uint8 kind (0 star, 4 or 6 reply), for a reply
+//     its address (4 or 16 bytes), uint32 rtt in microseconds, uint8 label count
+//     and the uint32 MPLS labels
+//   - PathReader: the paths of a binary stream
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use serde::Serialize;
+
+use crate::trace::{StopReason, Trace};
+
+pub const PATH_MAGIC: &[u8] = b"HSPATH1\n";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PathHop {
+    pub ttl: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<IpAddr>, // None for a star
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtt: Option<f64>, // in ms
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub star: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mpls: Vec<u32>, // the labels of the reply, top first
+}
+
+impl PathHop {
+    fn star(ttl: u8) -> Self {
+        PathHop {
+            ttl,
+            addr: None,
+            rtt: None,
+            star: true,
+            mpls: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TracePath {
+    pub monitor: IpAddr,
+    pub dst: IpAddr,
+    pub start: u32,
+    #[serde(serialize_with = "serialize_stop")]
+    pub stop: StopReason,
+    pub hops: Vec<PathHop>,
+}
+
+fn serialize_stop<S: serde::Serializer>(stop: &StopReason, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(stop)
+}
+
+impl TracePath {
+    // every reply in probe order, a star for each TTL from the first hop
+    // to the last probed one without any
+    pub fn new(trace: &Trace) -> Self {
+        let mut hops = Vec::new();
+        let last = trace.hop_count.max(trace.length() as u16);
+        let mut replies = trace.hops.iter().peekable();
+        for ttl in trace.firsthop as u16..=last {
+            let ttl = ttl as u8;
+            let mut star = true;
+            while let Some(h) = replies.next_if(|h| h.probe_ttl == ttl) {
+                hops.push(PathHop {
+                    ttl,
+                    addr: Some(h.addr),
+                    rtt: Some(h.rtt),
+                    star: false,
+                    mpls: h.mpls.iter().map(|m| m.label).collect(),
+                });
+                star = false;
+            }
+            if star {
+                hops.push(PathHop::star(ttl));
+            }
+        }
+        TracePath {
+            monitor: trace.src,
+            dst: trace.dst,
+            start: trace.start,
+            stop: trace.stop(),
+            hops,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn write_binary(&self, w: &mut dyn Write) -> io::Result<()> {
+        let family = match (self.monitor, self.dst) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => 4,
+            _ => 6,
+        };
+        w.write_all(&[family])?;
+        write_addr(w, &self.monitor, family)?;
+        write_addr(w, &self.dst, family)?;
+        w.write_all(&self.start.to_be_bytes())?;
+        w.write_all(&[self.stop as u8])?;
+        w.write_all(&(self.hops.len() as u16).to_be_bytes())?;
+        for h in &self.hops {
+            w.write_all(&[h.ttl])?;
+            match h.addr {
+                None => w.write_all(&[0])?,
+                Some(a) => {
+                    let kind = if a.is_ipv4() { 4 } else { 6 };
+                    w.write_all(&[kind])?;
+                    write_addr(w, &a, kind)?;
+                    let rtt = (h.rtt.unwrap_or(0.0) * 1000.0).round() as u32;
+                    w.write_all(&rtt.to_be_bytes())?;
+                    w.write_all(&[h.mpls.len() as u8])?;
+                    for l in &h.mpls {
+                        w.write_all(&l.to_be_bytes())?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// IPv4 as IPv4-mapped in a family 6 path
+fn write_addr(w: &mut dyn Write, a: &IpAddr, family: u8) -> io::Result<()> {
+    match (a, family) {
+        (IpAddr::V4(v4), 4) => w.write_all(&v4.octets()),
+        (IpAddr::V4(v4), _) => w.write_all(&v4.to_ipv6_mapped().octets()),
+        (IpAddr::V6(v6), _) => w.write_all(&v6.octets()),
+    }
+}
+
+// The paths of a binary stream, after its PATH_MAGIC.
+pub struct PathReader<R: Read> {
+    read: R,
+    done: bool, // the stream can't be followed after a broken record
+}
+
+impl<R: Read> PathReader<R> {
+    // reads and checks the magic
+    pub fn new(mut read: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        read.read_exact(&mut magic)?;
+        if magic != PATH_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a binary path file"));
+        }
+        Ok(PathReader { read, done: false })
+    }
+
+    fn bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut b = [0u8; N];
+        self.read.read_exact(&mut b)?;
+        Ok(b)
+    }
+
+    fn addr(&mut self, family: u8) -> io::Result<IpAddr> {
+        match family {
+            4 => Ok(IpAddr::V4(Ipv4Addr::from(self.bytes::<4>()?))),
+            6 => {
+                let a = Ipv6Addr::from(self.bytes::<16>()?);
+                Ok(a.to_ipv4_mapped().map_or(IpAddr::V6(a), IpAddr::V4))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad address family")),
+        }
+    }
+
+    fn path(&mut self, family: u8) -> io::Result<TracePath> {
+        let monitor = self.addr(family)?;
+        let dst = self.addr(family)?;
+        let start = u32::from_be_bytes(self.bytes()?);
+        let stop = StopReason::from_u8(self.bytes::<1>()?[0]);
+        let n = u16::from_be_bytes(self.bytes()?);
+        let mut hops = Vec::with_capacity(n as usize);
+        for _ in 0..n {
+            let [ttl, kind] = self.bytes()?;
+            if kind == 0 {
+                hops.push(PathHop::star(ttl));
+                continue;
+            }
+            let addr = self.addr(kind)?;
+            let rtt = u32::from_be_bytes(self.bytes()?) as f64 / 1000.0;
+            let labels = self.bytes::<1>()?[0];
+            let mut mpls = Vec::new();
+            for _ in 0..labels {
+                mpls.push(u32::from_be_bytes(self.bytes()?));
+            }
+            hops.push(PathHop {
+                ttl,
+                addr: Some(addr),
+                rtt: Some(rtt),
+                star: false,
+                mpls,
+            });
+        }
+        Ok(TracePath {
+            monitor,
+            dst,
+            start,
+            stop,
+            hops,
+        })
+    }
+}
+
+impl<R: Read> Iterator for PathReader<R> {
+    type Item = Result<TracePath, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut family = [0u8; 1];
+        match self.read.read(&mut family) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e.to_string()));
+            }
+        }
+        let r = self.path(family[0]).map_err(|e| {
+            self.done = true;
+            match e.kind() {
+                io::ErrorKind::UnexpectedEof => String::from("truncated path record"),
+                _ => e.to_string(),
+            }
+        });
+        Some(r)
+    }
+}
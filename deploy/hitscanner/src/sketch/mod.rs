@@ -0,0 +1,260 @@
+// Link delay estimation over all observations of a link
+//   - DelayEstimator: min, median or a percentile such as p90, see --delay
+//   - DelaySketch: a mergeable summary of the delay samples of a link
+//     - samples are counted in logarithmic bins, so a quantile is within
+//       ACCURACY of the true value, the min and max are exact
+//     - merging two sketches gives the sketch of all their samples,
+//       in any order, so linkmerge can estimate as if from the traces
+//     - text form MIN/MAX/ZEROS/BIN=COUNT,... e.g. 0.25/3.75/1/-69=2,66=1,
+//       with - for no bins
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+// relative accuracy of a quantile
+pub const ACCURACY: f64 = 0.01;
+
+// samples below are counted as 0
+const MIN_DELAY: f64 = 1e-6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DelayEstimator {
+    Min,
+    Percentile(u8), // 0 to 100 as given, e.g. p90, median is 50
+}
+
+impl FromStr for DelayEstimator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("unknown delay estimator {}, use min, median or a percentile, e.g. p90", s);
+        match s {
+            "min" => Ok(DelayEstimator::Min),
+            "median" => Ok(DelayEstimator::Percentile(50)),
+            _ => {
+                let p = s.strip_prefix('p').ok_or_else(err)?.parse::<u8>().map_err(|_| err())?;
+                if p > 100 {
+                    return Err(err());
+                }
+                Ok(DelayEstimator::Percentile(p))
+            }
+        }
+    }
+}
+
+impl fmt::Display for DelayEstimator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DelayEstimator::Min => write!(f, "min"),
+            DelayEstimator::Percentile(50) => write!(f, "median"),
+            DelayEstimator::Percentile(p) => write!(f, "p{}", p),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DelaySketch {
+    pub min: f64,
+    pub max: f64,
+    pub zeros: u32,
+    pub bins: BTreeMap<i32, u32>,
+}
+
+fn gamma() -> f64 {
+    (1.0 + ACCURACY) / (1.0 - ACCURACY)
+}
+
+impl DelaySketch {
+    // the sketch of a single sample
+    pub fn new(delay: f64) -> Self {
+        let mut s = Self {
+            min: delay,
+            max: delay,
+            zeros: 0,
+            bins: BTreeMap::new(),
+        };
+        s.count(delay, 1);
+        s
+    }
+
+    fn count(&mut self, delay: f64, n: u32) {
+        if delay < MIN_DELAY {
+            self.zeros += n;
+        } else {
+            let bin = (delay.ln() / gamma().ln()).ceil() as i32;
+            *self.bins.entry(bin).or_insert(0) += n;
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.zeros as u64 + self.bins.values().map(|&n| n as u64).sum::<u64>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn merge(&mut self, other: &DelaySketch) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.zeros += other.zeros;
+        for (bin, n) in &other.bins {
+            *self.bins.entry(*bin).or_insert(0) += n;
+        }
+    }
+
+    // the q-quantile, the lower median for an even number of samples,
+    // None for an empty sketch
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.is_empty() {
+            return None;
+        }
+        let rank = (q * (self.len() - 1) as f64).floor() as u64;
+        if rank < self.zeros as u64 {
+            return Some(self.min);
+        }
+        let mut seen = self.zeros as u64;
+        for (&bin, &n) in &self.bins {
+            seen += n as u64;
+            if rank < seen {
+                // the middle of the bin, within ACCURACY of any value in it
+                let v = 2.0 * gamma().powi(bin) / (gamma() + 1.0);
+                return Some(v.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    pub fn estimate(&self, estimator: DelayEstimator) -> f64 {
+        match estimator {
+            DelayEstimator::Min => self.min,
+            DelayEstimator::Percentile(p) => self.quantile(p as f64 / 100.0).unwrap_or(self.min),
+        }
+    }
+}
+
+impl fmt::Display for DelaySketch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}/{}/", self.min, self.max, self.zeros)?;
+        if self.bins.is_empty() {
+            return write!(f, "-");
+        }
+        let bins: Vec<String> = self.bins.iter().map(|(b, n)| format!("{}={}", b, n)).collect();
+        write!(f, "{}", bins.join(","))
+    }
+}
+
+impl FromStr for DelaySketch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("not a delay sketch: {}", s);
+        let f: Vec<&str> = s.split('/').collect();
+        if f.len() != 4 {
+            return Err(err());
+        }
+        let mut bins = BTreeMap::new();
+        if f[3] != "-" {
+            for kv in f[3].split(',') {
+                let (b, n) = kv.split_once('=').ok_or_else(err)?;
+                bins.insert(b.parse().map_err(|_| err())?, n.parse().map_err(|_| err())?);
+            }
+        }
+        let sketch = DelaySketch {
+            min: f[0].parse().map_err(|_| err())?,
+            max: f[1].parse().map_err(|_| err())?,
+            zeros: f[2].parse().map_err(|_| err())?,
+            bins,
+        };
+        // a sketch has at least the sample of one observation
+        if sketch.is_empty() {
+            return Err(format!("empty delay sketch: {}", s));
+        }
+        Ok(sketch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sketch(delays: &[f64]) -> DelaySketch {
+        let mut s = DelaySketch::new(delays[0]);
+        for &d in &delays[1..] {
+            s.merge(&DelaySketch::new(d));
+        }
+        s
+    }
+
+    #[test]
+    fn estimator_keeps_the_given_percentile() {
+        let names = [
+            ("min", "min"),
+            ("median", "median"),
+            ("p50", "median"),
+            ("p7", "p7"),
+            ("p90", "p90"),
+            ("p100", "p100"),
+        ];
+        for (text, shown) in names {
+            let e: DelayEstimator = text.parse().unwrap();
+            assert_eq!(e.to_string(), shown);
+            assert_eq!(shown.parse::<DelayEstimator>().unwrap(), e);
+        }
+        for bad in ["p101", "p7.5", "p-1", "mean", "90"] {
+            assert!(bad.parse::<DelayEstimator>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn quantiles_within_accuracy() {
+        let delays: Vec<f64> = (1..=100).map(|i| i as f64).collect();
+        let s = sketch(&delays);
+        assert_eq!(s.len(), 100);
+        assert_eq!(s.estimate(DelayEstimator::Min), 1.0);
+        for (p, exact) in [(50, 50.0), (90, 90.0), (0, 1.0), (100, 100.0)] {
+            let v = s.estimate(DelayEstimator::Percentile(p));
+            assert!((v - exact).abs() <= exact * ACCURACY, "p{}: {} vs {}", p, v, exact);
+        }
+    }
+
+    #[test]
+    fn zeros_and_merge_order() {
+        let a = sketch(&[0.0, 0.0, 5.0]);
+        let b = sketch(&[3.0, 7.5]);
+        let mut ab = a.clone();
+        ab.merge(&b);
+        let mut ba = b.clone();
+        ba.merge(&a);
+        assert_eq!(ab, ba);
+        assert_eq!(ab.zeros, 2);
+        assert_eq!(ab.quantile(0.0), Some(0.0));
+        assert_eq!(ab.min, 0.0);
+        assert_eq!(ab.max, 7.5);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let s = sketch(&[0.0, 0.25, 3.75, 3.7]);
+        let back: DelaySketch = s.to_string().parse().unwrap();
+        assert_eq!(back, s);
+        let zeros: DelaySketch = "0/0/2/-".parse().unwrap();
+        assert_eq!(zeros.quantile(0.5), Some(0.0));
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_sketches() {
+        for bad in ["0/0/0/-", "1/2/0/", "1/2/x/-", "1/2/0/5", "1/2/0/5=x", "1/2/0", ""] {
+            assert!(bad.parse::<DelaySketch>().is_err(), "{}", bad);
+        }
+        let empty = DelaySketch {
+            min: 0.0,
+            max: 0.0,
+            zeros: 0,
+            bins: BTreeMap::new(),
+        };
+        assert_eq!(empty.quantile(0.5), None);
+        assert_eq!(empty.estimate(DelayEstimator::Percentile(90)), 0.0);
+    }
+}
@@ -1,33 +1,28 @@
-use std::io::{self, BufRead};
-use std::net::Ipv4Addr;
+use hitscanner::compress::{self, Codec};
+use hitscanner::TraceReader;
+use itertools::Itertools;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
 
-fn main() -> io::Result<()> {
-    let stdin = io::stdin();
-    let mut lines_iter = stdin.lock().lines();
-    let mut p: Vec<String> = Vec::new();
-    let mut ip: Ipv4Addr;
-    let mut ip_lines: Vec<(u32, String)> = Vec::new();
+// reads traces from STDIN, --compress=gzip|zstd|xz|bz2 compresses the output
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut pargs = pico_args::Arguments::from_env();
+    let codec: Codec = pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain);
+    let mut ip_lines: Vec<(IpAddr, String)> = Vec::new();
 
-    while let Some(Ok(line)) = lines_iter.next() {
-        let l = line.trim().to_string();
-        if !l.is_empty() && p.len() > 0 && l.chars().nth(0).unwrap_or_default() == 't' {
-            ip = p[0].split_whitespace().nth(4).unwrap().parse().unwrap();
-            ip_lines.push((u32::from(ip), p.join("|")));
-            p.clear();
-        }
-        p.push(l);
+    for trace in TraceReader::open(&PathBuf::from("-"))? {
+        let t = trace?;
+        ip_lines.push((t.dst, t.to_string().lines().map(|l| l.trim()).join("|")));
     }
 
-    if !p.is_empty() {
-        ip = p[0].split_whitespace().nth(4).unwrap().parse().unwrap();
-        ip_lines.push((u32::from(ip), p.join("|")));
-    }
-
-    ip_lines.sort_unstable_by_key(|k| k.0);
+    ip_lines.sort_by_key(|k| k.0);
 
+    let mut out = compress::create(&PathBuf::from("-"), codec)?;
     for line in ip_lines {
-        println!("{}", line.1);
+        writeln!(out, "{}", line.1)?;
     }
+    out.finish()?;
 
     Ok(())
 }
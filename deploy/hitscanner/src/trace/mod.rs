@@ -0,0 +1,284 @@
+// Traceroute records shared by all binaries
+//   - Hop: one reply to a probe, a TTL without any reply has no hop
+//     - MplsLabel: an entry of the MPLS label stack of an ICMP extension (RFC 4950)
+//   - Trace: a traceroute from a monitor to a destination
+//     - StopReason: why it stopped, from warts or guessed from the hops of a text trace
+//     - displayed in the sc_warts2text format, with the start time in the header
+//       and a MPLS Label line for each label stack entry of a hop
+//   - TraceReader: streams the traces of a warts or warts2text input, compressed or not
+
+use std::fmt;
+use std::io::{BufRead, Result};
+use std::str::FromStr;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+use crate::compress;
+use crate::error::InputError;
+use crate::warts::{TextReader, WartsReader, WARTS_MAGIC};
+
+#[derive(Debug, Clone)]
+pub struct Hop {
+    pub addr: IpAddr,
+    pub probe_ttl: u8,
+    pub probe_id: u8,
+    pub reply_ttl: u8,
+    pub rtt: f64, // in ms
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub quoted_ttl: u8, // the IP TTL of the probe quoted in the reply, 0 if unknown
+    pub mpls: Vec<MplsLabel>, // the label stack of the reply, top first
+}
+
+impl Hop {
+    pub fn new(addr: IpAddr, probe_ttl: u8, rtt: f64) -> Self {
+        Self {
+            addr,
+            probe_ttl,
+            probe_id: 0,
+            reply_ttl: 0,
+            rtt,
+            icmp_type: 0,
+            icmp_code: 0,
+            quoted_ttl: 0,
+            mpls: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MplsLabel {
+    pub label: u32, // 20 bits
+    pub tc: u8,     // traffic class, 3 bits
+    pub s: bool,    // bottom of stack
+    pub ttl: u8,    // LSE TTL
+}
+
+impl MplsLabel {
+    // a 4-byte label stack entry
+    pub fn from_u32(v: u32) -> Self {
+        Self {
+            label: v >> 12,
+            tc: ((v >> 9) & 0x7) as u8,
+            s: (v >> 8) & 0x1 == 1,
+            ttl: (v & 0xFF) as u8,
+        }
+    }
+}
+
+// as in sc_warts2text: MPLS Label 16005 TC 0 S 1 TTL 1
+impl fmt::Display for MplsLabel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MPLS Label {} TC {} S {} TTL {}", self.label, self.tc, self.s as u8, self.ttl)
+    }
+}
+
+impl FromStr for MplsLabel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let err = || format!("malformed MPLS label: {}", s);
+        let f: Vec<&str> = s.split_whitespace().collect();
+        if f.len() != 9 || f[0] != "MPLS" || f[1] != "Label" || f[3] != "TC" || f[5] != "S" || f[7] != "TTL" {
+            return Err(err());
+        }
+        Ok(Self {
+            label: f[2].parse().map_err(|_| err())?,
+            tc: f[4].parse().map_err(|_| err())?,
+            s: f[6].parse::<u8>().map_err(|_| err())? == 1,
+            ttl: f[8].parse().map_err(|_| err())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub list_id: u32,
+    pub cycle_id: u32,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub start: u32, // unix timestamp in seconds
+    pub stop_reason: u8,
+    pub stop_data: u8,
+    pub hop_count: u16,
+    pub firsthop: u8,
+    pub hops: Vec<Hop>,
+}
+
+impl Trace {
+    pub fn new(src: IpAddr, dst: IpAddr, start: u32) -> Self {
+        Self {
+            list_id: 0,
+            cycle_id: 0,
+            src,
+            dst,
+            start,
+            stop_reason: 0,
+            stop_data: 0,
+            hop_count: 0,
+            firsthop: 1,
+            hops: Vec::new(),
+        }
+    }
+}
+
+// scamper's SCAMPER_TRACE_STOP_* values
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StopReason {
+    None,
+    Completed,
+    Unreach,
+    Icmp,
+    Loop,
+    GapLimit,
+    Error,
+    HopLimit,
+    Gss,
+    Halted,
+}
+
+impl StopReason {
+    pub fn from_u8(v: u8) -> Self {
+        match v {
+            1 => StopReason::Completed,
+            2 => StopReason::Unreach,
+            3 => StopReason::Icmp,
+            4 => StopReason::Loop,
+            5 => StopReason::GapLimit,
+            6 => StopReason::Error,
+            7 => StopReason::HopLimit,
+            8 => StopReason::Gss,
+            9 => StopReason::Halted,
+            _ => StopReason::None,
+        }
+    }
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            StopReason::None => "none",
+            StopReason::Completed => "completed",
+            StopReason::Unreach => "unreach",
+            StopReason::Icmp => "icmp",
+            StopReason::Loop => "loop",
+            StopReason::GapLimit => "gaplimit",
+            StopReason::Error => "error",
+            StopReason::HopLimit => "hoplimit",
+            StopReason::Gss => "gss",
+            StopReason::Halted => "halted",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Trace {
+    // the reply of the destination, if any
+    pub fn dst_reply(&self) -> Option<&Hop> {
+        self.hops.iter().find(|h| h.addr == self.dst)
+    }
+
+    // the TTL of the last reply, 0 for a trace without any
+    pub fn length(&self) -> u8 {
+        self.hops.iter().map(|h| h.probe_ttl).max().unwrap_or(0)
+    }
+
+    // The stop reason of warts, or for sc_warts2text, which has none, completed
+    // if the destination replied and gaplimit if the trace ends in stars.
+    pub fn stop(&self) -> StopReason {
+        if self.stop_reason != 0 {
+            return StopReason::from_u8(self.stop_reason);
+        }
+        if self.dst_reply().is_some() {
+            StopReason::Completed
+        } else if self.hop_count > self.length() as u16 {
+            StopReason::GapLimit
+        } else {
+            StopReason::None
+        }
+    }
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        let any = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        Self::new(any, any, 0)
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "traceroute from {} to {} {}", self.src, self.dst, self.start)?;
+        let mut hops = self.hops.iter().peekable();
+        let last_ttl = self.hops.iter().map(|h| h.probe_ttl as u16).max().unwrap_or(0);
+        // the labels of the first reply of a line follow it
+        let ext = |f: &mut fmt::Formatter, mpls: &[MplsLabel]| -> fmt::Result {
+            mpls.iter().try_for_each(|m| write!(f, "\n     {}", m))
+        };
+        for ttl in self.firsthop as u16..=self.hop_count.max(last_ttl) {
+            let mut line = String::new();
+            let mut addr: Option<IpAddr> = None;
+            let mut mpls: &[MplsLabel] = &[];
+            while let Some(h) = hops.next_if(|h| h.probe_ttl as u16 == ttl) {
+                if addr != Some(h.addr) {
+                    if addr.is_some() {
+                        write!(f, "\n{:2}{}", ttl, line)?;
+                        ext(f, mpls)?;
+                        line.clear();
+                    }
+                    line += &format!("  {}", h.addr);
+                    addr = Some(h.addr);
+                    mpls = &h.mpls;
+                }
+                line += &format!("  {:.3} ms", h.rtt);
+            }
+            if addr.is_none() {
+                line += "  *";
+            }
+            write!(f, "\n{:2}{}", ttl, line)?;
+            ext(f, mpls)?;
+        }
+        Ok(())
+    }
+}
+
+// traces from either a warts stream or sc_warts2text output,
+// told apart by the warts magic at the start of the input
+pub struct TraceReader {
+    traces: Box<dyn Iterator<Item = std::result::Result<Trace, InputError>>>,
+    file: String,
+}
+
+impl TraceReader {
+    // `reader` is already decompressed, see compress::reader
+    pub fn new(mut reader: Box<dyn BufRead>) -> Self {
+        let is_warts = match reader.fill_buf() {
+            Ok(b) => b.len() >= 2 && u16::from_be_bytes([b[0], b[1]]) == WARTS_MAGIC,
+            Err(_) => false,
+        };
+        let traces: Box<dyn Iterator<Item = std::result::Result<Trace, InputError>>> = if is_warts {
+            Box::new(WartsReader::new(reader).traces())
+        } else {
+            Box::new(TextReader::new(reader))
+        };
+        TraceReader {
+            traces,
+            file: String::from("-"),
+        }
+    }
+
+    // "-" for STDIN
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut reader = Self::new(compress::open(path)?);
+        reader.file = path.display().to_string();
+        Ok(reader)
+    }
+}
+
+impl Iterator for TraceReader {
+    type Item = std::result::Result<Trace, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.traces.next().map(|r| r.map_err(|e| e.in_file(&self.file)))
+    }
+}
@@ -2,25 +2,59 @@
 // =============================================================================
 // USAGE: see Usage below (./trace2link -h)
 // INPUT: a batch of traceroute data file names from STDIN or @ARGV
-//        the file format is .warts.gz or warts2text
-// INPUT: a batch of traceroute data file names from STDIN or @ARGV
-//        the file format is .warts.gz or warts2text
-// OUTPUT: CSV text
-//         1.in 2.out 3.is_dest 4.star 5.delay 6.freq 7.ttl 8.monitor
+//        the file format is warts or warts2text, plain or gzip/zstd/xz/bz2
+//        compressed, all detected by content
+// OUTPUT: CSV text, to STDOUT, or with -p PREFIX to
+//         PREFIX<input file name>.links for each input and the combined
+//         PREFIXtraceroute.links, with a .gz suffix for -z, .zst for --compress=zstd etc.
+//         the input file names must be unique and other than traceroute
+//         a #links header line first, see link::LinkHeader, unless --no-header
+//         --format jsonl|parquet|arrow writes the same links as JSON lines or typed
+//         columns instead, to .links.jsonl, .links.parquet etc., see linkio
+//         1.in 2.out 3.is_dest 4.star 5.delay 6.freq 7.ttl 8.monitor 9.firstseen 10.lastseen
 //         1. the IP address of the ingress interface, e.g., 1.2.3.4
 //         2. the IP address of the outgress interface, e.g., 5.6.7.8
 //         3. whether the outgress node is the destination, e.g., Y or N
 //         4. the number of anonymous (*) hops inbetween, e.g., 0 for directed link
-//         5. the minimal delay in ms > 0, e.g., 10
+//         5. the delay in ms >= 0, half the RTT difference of the two hops, the
+//            minimal one or the median, a percentile etc. of --delay, e.g., 10
 //         6. the cumulative frequence of link observed, e.g., 5000
 //         7. the minimal TTL of the ingress interface, e.g., 7
 //         8. the monoitor which observed the link at the minimal TTL, e.g., 9.0.1.2
+//         9. the earliest start time of the traces observing the link, e.g., 1677196800
+//         10. the latest start time of the traces observing the link, e.g., 1677283200
+//         11. with -M, the observations per monitor, e.g., 9.0.1.2=3,9.0.1.3=2
+//         12. with --loops=flag, the observations in or after a loop, e.g., 1
+//         then with --delay other than min, the sketch of all delays that linkmerge
+//         merges, see sketch::DelaySketch, e.g., 0.25/3.75/1/-69=2,66=1
+//         and with --rtt, the minimal RTT to the ingress and to the outgress interface
+//         and with --mpls, the observations with an MPLS tunnel at either end
+//         with --anon, also the links through placeholder nodes of the anonymous hops,
+//         *SRC_DST_START_TTL, and from the last responsive hop of a trace ending in
+//         stars to *end_DST, with the trailing stars in 4., see link::placeholders
+//         with --hops FILE, the MPLS tunnel annotations of the hops to FILE,
+//         one line per interface, see mpls::HopNote, e.g. 10.0.0.5 explicit 3 1 16005
+// NOTE:  a trace with a repeated IP is dropped, truncated before the repeat or
+//        kept with its looping links flagged, see --loops; the header counts them
+
+use hitscanner::compress::{self, Codec};
+use hitscanner::link::{
+    addlink, extract, last_hop, mergelinks, placeholders, LinkHeader, LoopPolicy, LoopStats, DELAYS_COLUMN,
+    LOOPS_COLUMN, MONITORS_COLUMN, MPLS_COLUMN, RTT_IN_COLUMN, RTT_OUT_COLUMN,
+};
+use hitscanner::linkio::{self, LinkFormat};
+use hitscanner::error::{OnError, Rejects};
+use hitscanner::mpls::{annotate, mergenotes, HopNote};
+use hitscanner::sketch::DelayEstimator;
+use hitscanner::{InOut, Link, LinkProp, TraceReader};
 
 use itertools::Itertools;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Read};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
 // command line arguments
 const HELP: &str = "\
@@ -28,16 +62,52 @@ Usage: trace2link.pl [OPTIONS] [files]
 
 When [files] is empty, read file names from STDIN
 OPTIONS:
--    read txt-format warts2text data from STDIN
+-    read warts or txt-format warts2text data from STDIN
 -h   print this help message
--p   the prefix of output file names
--z   output with gzip
+-j   the number of input files parsed in parallel, default 1
+-M   add the number of observations per monitor as the 11th column
+--anon        also link through placeholders of anonymous hops, *SRC_DST_START_TTL,
+              and from the last responsive hop of a trace ending in stars to *end_DST,
+              csv and jsonl only
+--delay       min|median|pNN estimator of the link delay, e.g. p90, default min,
+              other than min also writes the delay sketch column for linkmerge
+--rtt         add the minimal RTT to the ingress and the outgress interface
+--mpls        add the observations with an MPLS tunnel at either end of the link,
+              explicit (label stack) or implicit (quoted TTL above 1)
+--hops        file of the MPLS tunnel annotations of the hops, compressed by --compress
+--loops       drop|truncate|flag the links of a trace with a loop, default drop,
+              flag keeps them and counts the looping ones in the loops column
+-p   the prefix of output file names, e.g. out/ writes out/<file>.links
+     for each input file and the combined out/traceroute.links, instead
+     of the combined links to STDOUT, the input file names must be unique
+     and other than traceroute
+-z   output with gzip, same as --compress=gzip
+--compress    none|gzip|zstd|xz|bz2 compression of the output, default none
+--format      csv|jsonl|parquet|arrow output format, default csv,
+              parquet and arrow can't be used with --compress,
+              jsonl is for other tools, linkmerge can't read it back
+--no-header   write the legacy format without the #links header line,
+              not with --delay other than min, --rtt or --mpls
+--on-error    skip|fail|quarantine malformed traces, default fail
+--quarantine  file of the quarantined lines, default trace2link.rejected
 ";
 
 #[allow(dead_code)]
 struct AppArgs {
     prefix: Option<std::path::PathBuf>,
-    gzip: bool,
+    codec: Codec,
+    format: LinkFormat,
+    header: bool,
+    jobs: usize,
+    monitors: bool,
+    anon: bool,
+    delay: DelayEstimator,
+    rtt: bool,
+    mpls: bool,
+    hops: Option<PathBuf>,
+    loops: LoopPolicy,
+    on_error: OnError,
+    quarantine: PathBuf,
     inputs: Vec<std::ffi::OsString>,
 }
 
@@ -45,7 +115,7 @@ fn parse_path(s: &std::ffi::OsStr) -> Result<std::path::PathBuf, &'static str> {
     Ok(s.into())
 }
 
-fn getoption() -> Result<AppArgs, pico_args::Error> {
+fn getoption() -> Result<AppArgs, Box<dyn std::error::Error>> {
     let mut pargs = pico_args::Arguments::from_env();
 
     // Help has a higher priority and should be handled separately.
@@ -56,201 +126,305 @@ fn getoption() -> Result<AppArgs, pico_args::Error> {
 
     let args = AppArgs {
         prefix: pargs.opt_value_from_os_str(["-p", "--prefix"], parse_path)?,
-        gzip: pargs.contains(["-z", "--gzip"]),
+        codec: match (pargs.contains(["-z", "--gzip"]), pargs.opt_value_from_str("--compress")?) {
+            (_, Some(codec)) => codec,
+            (true, None) => Codec::Gzip,
+            (false, None) => Codec::Plain,
+        },
+        format: pargs.opt_value_from_str("--format")?.unwrap_or(LinkFormat::Csv),
+        header: !pargs.contains("--no-header"),
+        jobs: pargs.opt_value_from_str(["-j", "--jobs"])?.unwrap_or(1),
+        monitors: pargs.contains(["-M", "--monitors"]),
+        anon: pargs.contains("--anon"),
+        delay: pargs.opt_value_from_str("--delay")?.unwrap_or(DelayEstimator::Min),
+        rtt: pargs.contains("--rtt"),
+        mpls: pargs.contains("--mpls"),
+        hops: pargs.opt_value_from_os_str("--hops", parse_path)?,
+        loops: pargs.opt_value_from_str("--loops")?.unwrap_or(LoopPolicy::Drop),
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("trace2link.rejected")),
         inputs: pargs.finish(),
     };
 
+    // the legacy format reads at most the monitors and loops columns, see link::LEGACY_FIELDS
+    if !args.header {
+        let columns = [
+            (args.delay != DelayEstimator::Min, "--delay"),
+            (args.rtt, "--rtt"),
+            (args.mpls, "--mpls"),
+        ];
+        if let Some((_, option)) = columns.iter().find(|(on, _)| *on) {
+            return Err(format!("--no-header can't be used with {}, the legacy format has no column for it", option).into());
+        }
+    }
+
     Ok(args)
 }
 
-// I/O helpers
-fn filetype(path_string: &str) -> &str {
-    if path_string.ends_with("warts")
-        || path_string.ends_with("warts.gz")
-        || path_string.ends_with("warts.g")
-    {
-        return "BIN";
-    }
-    return "TXT";
+// where the links go, see -p, -z, --compress, --format and --no-header
+struct Output {
+    prefix: Option<PathBuf>,
+    codec: Codec,
+    format: LinkFormat,
+    header: bool,
 }
 
-fn openfile(path: &std::path::PathBuf) -> Box<dyn std::io::Read + 'static> {
-    let input: Box<dyn std::io::Read + 'static> = if path.as_os_str() == "-" {
-        Box::new(std::io::stdin())
-    } else {
-        let path_string = path.as_os_str().to_str().unwrap().to_string();
-        let mut cmd;
-        if filetype(&path_string) == "BIN" {
-            cmd = format!("{} | sc_warts2text", path_string);
-        } else {
-            cmd = format!("{}", path_string);
-        }
-
-        if path_string.ends_with(".gz") || path_string.ends_with(".g") {
-            cmd = format!("gzip -dc {}", cmd);
-        } else {
-            cmd = format!("cat {}", cmd);
+impl Output {
+    // PREFIX<name>.links(.jsonl|.parquet|...)(.gz|.zst|...), "-" for STDOUT
+    fn path(&self, name: &std::ffi::OsStr) -> PathBuf {
+        match &self.prefix {
+            Some(prefix) => {
+                let mut path = prefix.clone().into_os_string();
+                path.push(name);
+                path.push(".links");
+                path.push(self.format.suffix());
+                path.push(self.codec.suffix());
+                PathBuf::from(path)
+            }
+            None => PathBuf::from("-"),
         }
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-        let o = child.stdout.take().unwrap();
-        Box::new(o)
-    };
+    }
 
-    return input;
+    // the error is the message for the main thread to print
+    fn write(&self, name: &std::ffi::OsStr, header: &LinkHeader, links: &HashMap<InOut, LinkProp>) -> Result<(), String> {
+        let path = self.path(name);
+        let delay = header.delay;
+        let header = if self.header { Some(header) } else { None };
+        let ipv6 = links.keys().any(|io| io._in.contains(':') || io.out.contains(':'));
+        let r = linkio::create(&path, self.format, self.codec, header).and_then(|out| {
+            let mut out = out.ipv6(ipv6);
+            write_links(&mut out, links, delay)?;
+            out.finish()
+        });
+        r.map_err(|e| format!("can't write {}: {}", path.display(), e))
+    }
 }
 
-// sub-routines
-#[derive(Clone)]
-struct Link {
-    io: InOut,
-    prop: LinkProp,
+// the name of the own output file of an input, see -p
+fn output_name(input: &std::ffi::OsString) -> &std::ffi::OsStr {
+    match std::path::Path::new(input).file_name() {
+        Some(n) if input != "-" => n,
+        _ => "stdin".as_ref(),
+    }
 }
 
-impl Link {
-    fn new() -> Self {
-        Self {
-            io: InOut::new(),
-            prop: LinkProp::new(),
+// with -p, every input needs its own output file, apart from the combined one
+fn check_output_names(inputs: &[std::ffi::OsString]) -> Result<(), String> {
+    let mut names: HashMap<&std::ffi::OsStr, &std::ffi::OsString> = HashMap::new();
+    for input in inputs {
+        let name = output_name(input);
+        if name == "traceroute" {
+            return Err(format!(
+                "the output of {} would overwrite the combined traceroute links, rename it",
+                input.to_string_lossy()
+            ));
+        }
+        if let Some(other) = names.insert(name, input) {
+            return Err(format!(
+                "{} and {} would write the same output file {}, rename one of them",
+                other.to_string_lossy(),
+                input.to_string_lossy(),
+                name.to_string_lossy()
+            ));
         }
     }
+    Ok(())
 }
 
-#[derive(Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
-struct InOut {
-    _in: String,
-    out: String,
+// sub-routines
+fn write_links(
+    out: &mut linkio::LinkWriter,
+    links: &HashMap<InOut, LinkProp>,
+    delay: DelayEstimator,
+) -> std::io::Result<()> {
+    for key in links.keys().sorted() {
+        let mut link = Link {
+            io: key.clone(),
+            prop: links[key].clone(),
+        };
+        link.prop.estimate_delay(delay);
+        out.write(&link)?;
+    }
+    Ok(())
 }
 
-impl InOut {
-    fn new() -> Self {
-        Self {
-            _in: String::new(),
-            out: String::new(),
-        }
+// the hop annotations, sorted by interface
+fn write_notes(path: &Path, codec: Codec, notes: &HashMap<String, HopNote>) -> std::io::Result<()> {
+    let mut out = compress::create(path, codec)?;
+    for addr in notes.keys().sorted() {
+        writeln!(out, "{} {}", addr, notes[addr])?;
     }
+    out.finish()
 }
 
-#[derive(Clone)]
-struct LinkProp {
-    is_dest: bool,
-    star: u32,
-    delay: f64,
-    freq: u32,
-    ttl: u32,
-    monitor: String,
-    firstseen: u32,
-    lastseen: u32,
+// what is extracted from a trace, see -M, --anon, --delay, --rtt, --mpls, --hops and --loops
+#[derive(Clone, Copy)]
+struct Mode {
+    monitors: bool,
+    anon: bool,
+    delay: DelayEstimator,
+    rtt: bool,
+    mpls: bool,
+    hops: bool,
+    loops: LoopPolicy,
 }
 
-impl LinkProp {
-    fn new() -> Self {
-        Self {
-            is_dest: false,
-            star: 0,
-            delay: 0.0,
-            freq: 0,
-            ttl: 0,
-            monitor: String::new(),
-            firstseen: 0,
-            lastseen: 0,
-        }
-    }
+fn new_header(mode: Mode) -> LinkHeader {
+    let mut header = LinkHeader::new(concat!("trace2link/", env!("CARGO_PKG_VERSION")))
+        .with_column(MONITORS_COLUMN, mode.monitors)
+        .with_column(LOOPS_COLUMN, mode.loops == LoopPolicy::Flag)
+        .with_column(DELAYS_COLUMN, mode.delay != DelayEstimator::Min)
+        .with_column(RTT_IN_COLUMN, mode.rtt)
+        .with_column(RTT_OUT_COLUMN, mode.rtt)
+        .with_column(MPLS_COLUMN, mode.mpls);
+    header.delay = mode.delay;
+    header.loops = Some(LoopStats::new(mode.loops));
+    header
 }
 
-fn addlink(link: &Vec<Link>, links: &mut HashMap<InOut, LinkProp>) {
-    for l in link {
-        if links.contains_key(&l.io) {
-            let a = links.get_mut(&l.io).unwrap();
-            if l.prop.is_dest == false {
-                a.is_dest = false
-            };
-            if a.star > l.prop.star {
-                a.star = l.prop.star
-            };
-            if a.delay > l.prop.delay {
-                a.delay = l.prop.delay
-            };
-            a.freq += 1;
-            if a.ttl > l.prop.ttl || (a.ttl == l.prop.ttl && l.prop.monitor < a.monitor) {
-                a.monitor = l.prop.monitor.clone();
-                a.ttl = l.prop.ttl;
+fn process(
+    traces: TraceReader,
+    mode: Mode,
+    links: &mut HashMap<InOut, LinkProp>,
+    notes: &mut HashMap<String, HopNote>,
+    header: &mut LinkHeader,
+    rejects: &Mutex<Rejects>,
+) -> Result<(), String> {
+    for trace in traces {
+        let trace = match trace {
+            Ok(t) => t,
+            Err(e) => {
+                rejects.lock().unwrap().reject(&e).map_err(|e| e.to_string())?;
+                continue;
+            }
+        };
+        header.observe(&trace);
+        if mode.hops {
+            annotate(&trace, notes);
+        }
+        let (mut link, is_loop) = extract(&trace);
+        mode.loops.apply(&mut link, is_loop, header.loops.as_mut().unwrap());
+        if mode.anon {
+            let anon = placeholders(&trace, &link);
+            link.extend(anon);
+            // the last hop is past the loop of a looping trace: flag keeps it
+            // marked, drop and truncate cut it with the rest of the trace
+            if let Some(mut l) = last_hop(&trace) {
+                if is_loop.is_some() {
+                    header.loops.as_mut().unwrap().links += 1;
+                }
+                if mode.loops == LoopPolicy::Flag {
+                    l.prop.loops = Some(is_loop.is_some() as u32);
+                    link.push(l);
+                } else if is_loop.is_none() {
+                    link.push(l);
+                }
+            }
+        }
+        for l in link.iter_mut() {
+            if mode.monitors {
+                l.prop.count_monitors();
+            }
+            if mode.delay != DelayEstimator::Min {
+                l.prop.sketch_delay();
+            }
+            if !mode.rtt {
+                l.prop.rtt = None;
+            }
+            if !mode.mpls {
+                l.prop.mpls = None;
             }
-            if a.firstseen > l.prop.firstseen {
-                a.firstseen = l.prop.firstseen
-            };
-            if a.lastseen < l.prop.lastseen {
-                a.lastseen = l.prop.lastseen
-            };
-        } else {
-            links.insert(l.io.clone(), l.prop.clone());
         }
+        addlink(&link, links);
     }
+    Ok(())
 }
 
-fn process(read: &mut Box<dyn std::io::Read>, links: &mut HashMap<InOut, LinkProp>) {
-    let (mut dest, mut start, mut last, mut node, mut link, mut is_loop): (
-        String,
-        u32,
-        Vec<String>,
-        HashMap<String, bool>,
-        Vec<Link>,
-        bool,
-    ) = (
-        String::from(""),
-        0,
-        Vec::new(),
-        HashMap::new(),
-        Vec::new(),
-        false,
-    );
+// with -p the links of each input are also written to their own file,
+// an unreadable input or unwritable output is an error for the caller
+fn process_file(
+    input: &std::ffi::OsString,
+    output: &Output,
+    mode: Mode,
+    links: &mut HashMap<InOut, LinkProp>,
+    notes: &mut HashMap<String, HopNote>,
+    header: &mut LinkHeader,
+    rejects: &Mutex<Rejects>,
+) -> Result<(), String> {
+    let path = PathBuf::from(input);
+    let traces = TraceReader::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if output.prefix.is_none() {
+        return process(traces, mode, links, notes, header, rejects);
+    }
+    let mut file_links: HashMap<InOut, LinkProp> = HashMap::new();
+    let mut file_header = new_header(mode);
+    process(traces, mode, &mut file_links, notes, &mut file_header, rejects)?;
+    output.write(output_name(input), &file_header, &file_links)?;
+    mergelinks(file_links, links);
+    header.merge(&file_header);
+    Ok(())
+}
 
-    let mut l = Link::new();
+// Each worker takes the next unread input file and builds its own link map,
+// the maps are then merged with the same rules as addlink. The merge is
+// order-independent, so the output is the same as with a single thread.
+// The first error stops the workers and is returned to the main thread.
+type Extracted = (HashMap<InOut, LinkProp>, HashMap<String, HopNote>, LinkHeader);
 
-    for line in BufReader::new(read).lines() {
-        let ll = line.unwrap();
-        let line = ll.trim();
-        let f: Vec<String> = line.split_whitespace().map(|x| x.to_string()).collect();
-        if &f[1] == "*" {
-            l.prop.star += 1;
-            continue;
-        }
-        if f[0].chars().next().unwrap() == 't' {
-            if !is_loop {
-                addlink(&link, links);
-            }
-            l.prop.monitor = f[2].to_string();
-            dest = f[4].clone();
-            start = f[5].parse::<u32>().unwrap();
-            node = HashMap::new();
-            link = Vec::new();
-            is_loop = false;
-        } else if last.len() > 1 && last[1] != "from" && f[1] != last[1] {
-            if node.contains_key(&f[1]) {
-                is_loop = true;
+fn process_parallel(
+    inputs: &[std::ffi::OsString],
+    output: &Output,
+    jobs: usize,
+    mode: Mode,
+    rejects: &Mutex<Rejects>,
+) -> Result<Extracted, String> {
+    let next = AtomicUsize::new(0);
+    let mut links: HashMap<InOut, LinkProp> = HashMap::new();
+    let mut notes: HashMap<String, HopNote> = HashMap::new();
+    let mut header = new_header(mode);
+    thread::scope(|s| {
+        let workers: Vec<_> = (0..jobs.min(inputs.len()))
+            .map(|_| {
+                s.spawn(|| {
+                    let mut links: HashMap<InOut, LinkProp> = HashMap::new();
+                    let mut notes: HashMap<String, HopNote> = HashMap::new();
+                    let mut header = new_header(mode);
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= inputs.len() {
+                            break;
+                        }
+                        if let Err(e) =
+                            process_file(&inputs[i], output, mode, &mut links, &mut notes, &mut header, rejects)
+                        {
+                            // no more inputs for the other workers
+                            next.store(inputs.len(), Ordering::Relaxed);
+                            return Err(e);
+                        }
+                    }
+                    Ok((links, notes, header))
+                })
+            })
+            .collect();
+        let mut error = None;
+        for w in workers {
+            match w.join().unwrap() {
+                Ok((l, n, h)) => {
+                    mergelinks(l, &mut links);
+                    mergenotes(n, &mut notes);
+                    header.merge(&h);
+                }
+                Err(e) => error = error.or(Some(e)),
             }
-            node.insert(f[1].to_string(), true);
-            l.io._in = last[1].to_string();
-            l.io.out = f[1].to_string();
-            l.prop.is_dest = if l.io.out == dest { true } else { false };
-            l.prop.delay = (f[2].parse::<f64>().unwrap() - last[2].parse::<f64>().unwrap()) / 2.0;
-            l.prop.delay = l.prop.delay.max(0.0);
-            l.prop.freq = 1;
-            l.prop.ttl = last[0].parse::<u32>().unwrap();
-            l.prop.firstseen = start;
-            l.prop.lastseen = start;
-            link.push(l.clone());
         }
-        l.prop.star = 0;
-        last = f.clone();
-    }
-    if !is_loop {
-        addlink(&link, links);
-    }
+        match error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    })?;
+    Ok((links, notes, header))
 }
 
 fn main() {
@@ -262,30 +436,82 @@ fn main() {
         }
     };
 
-    if args.inputs.len() <= 0 {
+    if args.inputs.is_empty() {
         args.inputs = vec![std::ffi::OsString::from("-")];
     }
+    if args.jobs == 0 {
+        eprintln!("Error: -j must be at least 1.");
+        std::process::exit(1);
+    }
+    if args.format.is_columnar() && args.codec != Codec::Plain {
+        eprintln!("Error: --format {} can't be used with --compress or -z.", args.format);
+        std::process::exit(1);
+    }
+    if args.format.is_columnar() && args.anon {
+        eprintln!("Error: --format {} can't be used with --anon, placeholders are not IP addresses.", args.format);
+        std::process::exit(1);
+    }
 
-    let mut links: HashMap<InOut, LinkProp> = HashMap::new();
-    for input in args.inputs {
-        let mut file = openfile(&PathBuf::from(&input));
-        process(file.by_ref(), &mut links);
+    let output = Output {
+        prefix: args.prefix,
+        codec: args.codec,
+        format: args.format,
+        header: args.header,
+    };
+    let mode = Mode {
+        monitors: args.monitors,
+        anon: args.anon,
+        delay: args.delay,
+        rtt: args.rtt,
+        mpls: args.mpls,
+        hops: args.hops.is_some(),
+        loops: args.loops,
+    };
+    if output.prefix.is_some() {
+        if let Err(e) = check_output_names(&args.inputs) {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
     }
+    let rejects = match Rejects::new(args.on_error, &args.quarantine) {
+        Ok(r) => Mutex::new(r),
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    let r = if args.jobs > 1 {
+        process_parallel(&args.inputs, &output, args.jobs, mode, &rejects)
+    } else {
+        let mut links: HashMap<InOut, LinkProp> = HashMap::new();
+        let mut notes: HashMap<String, HopNote> = HashMap::new();
+        let mut header = new_header(mode);
+        args.inputs
+            .iter()
+            .try_for_each(|input| process_file(input, &output, mode, &mut links, &mut notes, &mut header, &rejects))
+            .map(|_| (links, notes, header))
+    };
+    let (links, notes, header) = match r {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = rejects.lock().unwrap().summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
+    }
+    header.loops.as_ref().unwrap().summary("trace2link");
 
-    for key in links.keys().sorted() {
-        let value = &links[key];
-        println!(
-            "{} {} {} {} {:.3} {} {} {} {} {}",
-            key._in,
-            key.out,
-            if value.is_dest { "Y" } else { "N" },
-            value.star,
-            value.delay,
-            value.freq,
-            value.ttl,
-            value.monitor,
-            value.firstseen,
-            value.lastseen
-        );
+    if let Err(e) = output.write("traceroute".as_ref(), &header, &links) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
+    if let Some(path) = &args.hops {
+        if let Err(e) = write_notes(path, args.codec, &notes) {
+            eprintln!("Error: can't write {}: {}.", path.display(), e);
+            std::process::exit(1);
+        }
     }
 }
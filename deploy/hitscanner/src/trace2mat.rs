@@ -1,50 +1,216 @@
-mod iputils;
-
-use iputils::IPLabeller;
+use hitscanner::alias::Aliases;
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{read_line, Format, InputError, LineError, OnError, Rejects};
+use hitscanner::iputils::{area_codes, host_mask, DbManifest, IPLabeller, IPRange, Pfx2As};
+use hitscanner::link::{extract, LoopPolicy, LoopStats};
+use hitscanner::{Link, TraceReader};
 use trie::common::{NoMeta, Prefix};
 
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
-use std::fs::File;
-use std::io::Write;
-use std::io::{BufRead, BufReader};
-use std::net::Ipv4Addr;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-
-use crate::iputils::IPRange;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 const HELP: &str = "\
 Usage: trace2mat [OPTIONS] [files]
 
 When [files] is empty, read file names from STDIN
 OPTIONS:
-    -b         path to routeviews.csv
+    -b         path to routeviews.csv, a routeviews pfx2as file, e.g. of CAIDA, for the
+               origin ASN of rows and columns, 4_5 for a prefix with more than one origin
     -g         path to merged.db / merged.csv
+    -m         the manifest of merged.db written by dbmerge -m, default merged.db.manifest
+               if it exists, otherwise the databases are counted in merged.db, named db0, db1, ...
     -i         path to .iface
-    -a         country code (ISO 3166-1 alpha-2 standard)
-OUTPUTS: output as a sparse matrix
-    row.csv    each row represent a trace destination: number,IP,signature,key
-    col.csv    each col represent a router: number,IP
-    mat.csv    each line represent a 1 in matrix: row, col
+    --aliases  path to the aliases of the dealias step, pairs of addresses of a router,
+               the columns are then routers, named by their lowest address; a router is
+               a column if any of its interfaces passes -i, -a, --min-votes and --asn,
+               and a trace traversing any of its interfaces traverses it
+    -a         country codes (ISO 3166-1 alpha-2 standard) or regions, comma separated,
+               e.g. HK, CN,HK,MO or EU, regions are EU, EEA, ASEAN and GCC
+    --min-votes  the databases that must place a router in the area for it to be a column,
+               default 1, i.e. any
+    --asn      only routers originated by these ASNs are columns, e.g. 4134,4809, with -b,
+               as well as in the -a area
+    --row-granularity  ip|/N|geo-prefix|asn one row per destination, default ip, or per
+               destination /N prefix, e.g. /24, per merged db prefix or per origin ASN of -b,
+               a row then has the routers of any of its destinations
+    --counts   the destinations of the row traversing the router as the value of each cell
+    -o         the output directory, created if missing, default the current one
+    -p         the prefix of output file names, e.g. HK- writes HK-rows.csv
+    --format   csv|mtx the matrix as mat.csv or as a Matrix Market mat.mtx, default csv
+    --compress none|gzip|zstd|xz|bz2 compression of the outputs, default none
+    --loops    drop|truncate|flag the links of a trace with a loop, default truncate,
+               flag keeps all of them
+    --on-error    skip|fail|quarantine malformed traces and ifaces lines, default fail;
+                  a trace with an address the -g db doesn't cover is rejected too,
+                  the address is the quarantined line
+    --quarantine  file of the quarantined lines, default trace2mat.rejected
+INPUTS: traces, the ifaces and the db file may be gzip/zstd/xz/bz2 compressed
+        the matrix is IPv4 only, IPv6 traces are skipped and counted on STDERR
+OUTPUTS: output as a sparse matrix, each file written to a temporary file and
+renamed when complete, so parallel runs with different -o or -p don't clobber
+each other and a failed run leaves no partial file
+    rows.csv   each row represent a trace destination: number,IP,signature,key(,asn with -b)
+               after a header line naming the database of each signature digit, e.g.
+               #rows version=1 tool=trace2mat/0.1.0 columns=number,ip,signature,key granularity=ip area=HK votes=1 dbs=2 names=a.db,b.db
+               a signature digit is 1 if its database places the address in the area
+               with --row-granularity other than ip, each row represent a group of destinations:
+               number,group,signature,key(,asn),dsts, e.g. 3,10.0.1.0/24,110,10.0.0.0/16,4134,17
+               the signature digit is 1 if its database places any destination in the area,
+               key and asn are of its lowest destination and dsts counts its destinations
+    cols.csv   each col represent a router: number,IP(,asn with -b)(,interfaces with --aliases)
+               interfaces: the addresses of the router in the alias file, e.g. 10.0.0.1;10.0.0.5
+               with --aliases, the asn is that of the lowest interface that makes the
+               router a column, which may not be its IP
+    mat.csv    each line represent a 1 in matrix: row, col(, count with --counts)
+    mat.mtx    instead of mat.csv with --format mtx: a coordinate pattern matrix, or an
+               integer one with --counts, 1-based, rows.csv and cols.csv are its row and
+               column metadata
 EXAMPLE:
-    cat sorted_traceroute_lines.txt | trace2mat -b routeviews.csv -g merged.db -i ifaces -a HK
+    cat sorted_traceroute_lines.txt | trace2mat -b routeviews.csv -g merged.db -i ifaces -a HK -o out -p HK-
 ";
 
-fn parse_sig(db_geos: &String, db_num: usize, area: &str) -> String {
+// the signature of a merged db annotation, e.g. 0,CN,2,HK, None if it is malformed
+fn parse_sig(db_geos: &str, db_num: usize, area: &BTreeSet<String>) -> Option<String> {
     let mut sig = vec!['0'; db_num];
     let f: Vec<&str> = db_geos.split(",").collect();
-    for i in (0..f.len()).step_by(2) {
-        sig[f[i].parse::<usize>().unwrap()] = if f[i + 1] == area { '1' } else { '0' }
+    for pair in f.chunks(2) {
+        let [i, geo] = pair else { return None };
+        let s = sig.get_mut(i.parse::<usize>().ok()?)?;
+        *s = if area.contains(*geo) { '1' } else { '0' };
+    }
+    Some(sig.iter().collect())
+}
+
+// the databases placing an address in the area
+fn votes(sig: &str) -> usize {
+    sig.chars().filter(|&c| c == '1').count()
+}
+
+// what a row of the matrix stands for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Granularity {
+    Ip,
+    Prefix(u8), // e.g. /24
+    GeoPrefix,  // the merged db prefix of the destination
+    Asn,        // the origin ASNs of the destination, needs -b
+}
+
+impl FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip" => Ok(Granularity::Ip),
+            "geo-prefix" => Ok(Granularity::GeoPrefix),
+            "asn" => Ok(Granularity::Asn),
+            _ => match s.strip_prefix('/').map(|l| l.parse::<u8>()) {
+                Some(Ok(len)) if len <= 32 => Ok(Granularity::Prefix(len)),
+                _ => Err(format!("unknown row granularity {}, use ip, /N, geo-prefix or asn", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Granularity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Granularity::Ip => write!(f, "ip"),
+            Granularity::Prefix(len) => write!(f, "/{}", len),
+            Granularity::GeoPrefix => write!(f, "geo-prefix"),
+            Granularity::Asn => write!(f, "asn"),
+        }
+    }
+}
+
+// the row of a destination, sorted by address, or by ASN
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum RowKey {
+    Ip(Ipv4Addr),
+    Prefix(Ipv4Addr, u8),
+    Origin(Vec<u32>), // empty for an address without an origin
+}
+
+impl fmt::Display for RowKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowKey::Ip(ip) => write!(f, "{}", ip),
+            RowKey::Prefix(net, len) => write!(f, "{}/{}", net, len),
+            RowKey::Origin(asns) if asns.is_empty() => write!(f, "-"),
+            RowKey::Origin(asns) => write!(f, "{}", asns.iter().join("_")),
+        }
+    }
+}
+
+fn row_key(ctx: &Context, dst: Ipv4Addr) -> Result<RowKey, InputError> {
+    Ok(match ctx.granularity {
+        Granularity::Ip => RowKey::Ip(dst),
+        Granularity::Prefix(len) => {
+            let net = u32::from(dst) & !(host_mask(32 - len) as u32);
+            RowKey::Prefix(Ipv4Addr::from(net), len)
+        }
+        Granularity::GeoPrefix => {
+            let (net, len, _) = geo_range(ctx, dst)?;
+            RowKey::Prefix(net, len)
+        }
+        Granularity::Asn => {
+            let origins = origin(ctx.as_labeller, dst).unwrap();
+            RowKey::Origin(origins.split('_').filter_map(|a| a.parse().ok()).collect())
+        }
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MatFormat {
+    Csv,
+    Mtx,
+}
+
+impl FromStr for MatFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(MatFormat::Csv),
+            "mtx" => Ok(MatFormat::Mtx),
+            _ => Err(format!("unknown format {}, use csv or mtx", s)),
+        }
+    }
+}
+
+impl fmt::Display for MatFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MatFormat::Csv => write!(f, "csv"),
+            MatFormat::Mtx => write!(f, "mtx"),
+        }
     }
-    sig.iter().collect()
 }
 
 #[allow(dead_code)]
 struct AppArgs {
+    bgp: Option<PathBuf>,
+    asns: Option<String>,
     geo: PathBuf,
+    manifest: Option<PathBuf>,
     iface: PathBuf,
-    area: PathBuf,
+    aliases: Option<PathBuf>,
+    area: String,
+    min_votes: usize,
+    outdir: PathBuf,
+    prefix: std::ffi::OsString,
+    granularity: Granularity,
+    counts: bool,
+    format: MatFormat,
+    codec: Codec,
+    loops: LoopPolicy,
+    on_error: OnError,
+    quarantine: PathBuf,
     inputs: Vec<std::ffi::OsString>,
 }
 
@@ -62,200 +228,221 @@ fn getoption() -> Result<AppArgs, pico_args::Error> {
     }
 
     let args = AppArgs {
+        bgp: pargs.opt_value_from_os_str(["-b", "--bgp"], parse_path)?,
+        asns: pargs.opt_value_from_str("--asn")?,
         geo: pargs.value_from_os_str(["-g", "--geo"], parse_path)?,
+        manifest: pargs.opt_value_from_os_str(["-m", "--manifest"], parse_path)?,
         iface: pargs.value_from_os_str(["-i", "--iface"], parse_path)?,
-        area: pargs.value_from_os_str(["-a", "--area"], parse_path)?,
+        aliases: pargs.opt_value_from_os_str("--aliases", parse_path)?,
+        area: pargs.value_from_str(["-a", "--area"])?,
+        min_votes: pargs.opt_value_from_str("--min-votes")?.unwrap_or(1),
+        outdir: pargs
+            .opt_value_from_os_str(["-o", "--outdir"], parse_path)?
+            .unwrap_or_default(),
+        prefix: pargs
+            .opt_value_from_os_str(["-p", "--prefix"], parse_path)?
+            .map(|p| p.into_os_string())
+            .unwrap_or_default(),
+        granularity: pargs.opt_value_from_str("--row-granularity")?.unwrap_or(Granularity::Ip),
+        counts: pargs.contains("--counts"),
+        format: pargs.opt_value_from_str("--format")?.unwrap_or(MatFormat::Csv),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
+        loops: pargs.opt_value_from_str("--loops")?.unwrap_or(LoopPolicy::Truncate),
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("trace2mat.rejected")),
         inputs: pargs.finish(),
     };
 
     Ok(args)
 }
 
-#[derive(Clone, Eq, Hash, PartialEq, PartialOrd, Ord, Debug)]
-struct InOut {
-    _in: String,
-    out: String,
+// the origin ASNs of an address, - for none, None without -b
+fn origin(as_labeller: Option<&IPLabeller<Pfx2As>>, ip: Ipv4Addr) -> Option<String> {
+    let p = Prefix4NoMeta::new(ip.into(), 32);
+    as_labeller.map(|l| match l.match_pfx(&p) {
+        Some(r) => r.meta.clone().unwrap(),
+        None => String::from("-"),
+    })
 }
 
-impl InOut {
-    fn new() -> Self {
-        Self {
-            _in: String::new(),
-            out: String::new(),
-        }
+// an empty AS set lets every router in
+fn in_as_set(as_labeller: Option<&IPLabeller<Pfx2As>>, as_set: &HashSet<u32>, ip: Ipv4Addr) -> bool {
+    if as_set.is_empty() {
+        return true;
     }
+    let origins = origin(as_labeller, ip).unwrap();
+    origins.split('_').any(|a| a.parse().is_ok_and(|a| as_set.contains(&a)))
+}
+
+// the read-only configuration of a run, see -a, --min-votes, --asn, -i, -g, -b,
+// --aliases, --row-granularity and --loops
+struct Context<'a> {
+    area: &'a BTreeSet<String>,
+    min_votes: usize,
+    db_num: usize,
+    as_set: &'a HashSet<u32>,
+    ifaces: &'a HashSet<Ipv4Addr>,
+    geo_labeller: &'a IPLabeller<'a, IPRange>,
+    geo_file: &'a str,
+    as_labeller: Option<&'a IPLabeller<'a, Pfx2As>>,
+    aliases: Option<&'a Aliases>,
+    granularity: Granularity,
+    loops: LoopPolicy,
+}
+
+// the merged db range of an address and its annotation, an address the db
+// doesn't cover is rejected with the trace, see process()
+fn geo_range<'a>(ctx: &'a Context, ip: Ipv4Addr) -> Result<(Ipv4Addr, u8, &'a str), InputError> {
+    let reject = |msg: &str| {
+        InputError::new(Format::GeoDb, 0, &format!("{} {}", msg, ip))
+            .in_file(ctx.geo_file)
+            .with_raw(&ip.to_string())
+    };
+    let r = ctx
+        .geo_labeller
+        .match_pfx(&Prefix4NoMeta::new(ip.into(), 32))
+        .ok_or_else(|| reject("no range covers"))?;
+    let db_geos = r.meta.as_ref().ok_or_else(|| reject("no annotation for"))?;
+    Ok((Ipv4Addr::from(r.net), r.len, db_geos))
+}
+
+// the signature and the merged db prefix of an address
+fn geo(ctx: &Context, ip: Ipv4Addr) -> Result<(String, String), InputError> {
+    let (net, len, db_geos) = geo_range(ctx, ip)?;
+    let sig = parse_sig(db_geos, ctx.db_num, ctx.area).ok_or_else(|| {
+        InputError::new(Format::GeoDb, 0, &format!("malformed annotation {} of {}", db_geos, ip))
+            .in_file(ctx.geo_file)
+            .with_raw(&ip.to_string())
+    })?;
+    Ok((sig, format!("{}/{}", net, len)))
 }
 
-#[derive(Clone, Debug)]
-struct Link {
-    io: InOut,
+// whether an interface makes its router a column, see -i, -a, --min-votes and --asn;
+// an alias the merged db doesn't cover is in no area
+fn is_col(ctx: &Context, ip: Ipv4Addr) -> bool {
+    ctx.ifaces.contains(&ip)
+        && geo(ctx, ip).is_ok_and(|(sig, _)| votes(&sig) >= ctx.min_votes)
+        && in_as_set(ctx.as_labeller, ctx.as_set, ip)
 }
 
-impl Link {
-    fn new() -> Self {
-        Self { io: InOut::new() }
+// the router of an interface, the interface itself without --aliases
+fn router(ctx: &Context, ip: Ipv4Addr) -> Ipv4Addr {
+    match ctx.aliases.map(|a| a.router(IpAddr::V4(ip))) {
+        Some(IpAddr::V4(r)) => r,
+        _ => ip,
     }
 }
 
-fn add_link(
-    area: &str,
-    link: &[Link],
-    dst: Ipv4Addr,
-    row: &mut Vec<u64>,
-    col: &mut Vec<u64>,
-    dst2row: &mut HashMap<Ipv4Addr, u64>,
-    rtr2col: &mut HashMap<Ipv4Addr, u64>,
-    nodes: &mut HashMap<Ipv4Addr, (String, String)>,
-    ifaces: &HashSet<Ipv4Addr>,
-    geo_labeller: &IPLabeller<IPRange>,
-) {
-    if !dst2row.contains_key(&dst) {
-        dst2row.insert(dst, dst2row.len() as u64);
+// A router is a column if any of its interfaces is, the lowest of them is the
+// one its cols.csv line is labelled by. None if it is not a column.
+fn label(ctx: &Context, rtr: Ipv4Addr) -> Option<Ipv4Addr> {
+    let members = match ctx.aliases {
+        Some(a) => a.members(IpAddr::V4(rtr)),
+        None => vec![IpAddr::V4(rtr)],
+    };
+    members.into_iter().find_map(|a| match a {
+        IpAddr::V4(a) if is_col(ctx, a) => Some(a),
+        _ => None,
+    })
+}
+
+// the matrix so far, a (row, col) entry for each router of each trace
+#[derive(Default)]
+struct Matrix {
+    row: Vec<u64>,
+    col: Vec<u64>,
+    dst2row: HashMap<Ipv4Addr, u64>,
+    rtr2col: HashMap<Ipv4Addr, u64>,
+    nodes: HashMap<Ipv4Addr, (String, String)>, // of each address, see geo()
+    keys: HashMap<Ipv4Addr, RowKey>,              // of each destination, see row_key()
+    labels: HashMap<Ipv4Addr, Option<Ipv4Addr>>, // of each router, see label()
+}
+
+// a trace with an address the merged db doesn't cover adds nothing to the matrix
+fn add_link(ctx: &Context, link: &[Link], dst: Ipv4Addr, mat: &mut Matrix) -> Result<(), InputError> {
+    let ips: Vec<Ipv4Addr> = link
+        .iter()
+        .flat_map(|l| [&l.io._in, &l.io.out])
+        .map(|a| a.parse().unwrap())
+        .collect();
+    for ip in std::iter::once(dst).chain(ips.iter().copied()) {
+        if let Entry::Vacant(e) = mat.nodes.entry(ip) {
+            e.insert(geo(ctx, ip)?);
+        }
+    }
+    if let Entry::Vacant(e) = mat.keys.entry(dst) {
+        e.insert(row_key(ctx, dst)?);
     }
 
+    let n = mat.dst2row.len() as u64;
+    let dst_row = *mat.dst2row.entry(dst).or_insert(n);
     let mut t: HashSet<Ipv4Addr> = HashSet::new();
-    for l in link {
-        for ip in [
-            l.io._in.parse::<Ipv4Addr>().unwrap(),
-            l.io.out.parse::<Ipv4Addr>().unwrap(),
-        ] {
-            let p = Prefix4NoMeta::new(ip.into(), 32);
-            let r = geo_labeller.match_pfx(&p);
-            let db_geos = r.unwrap().meta.as_ref().unwrap();
-            let sig = parse_sig(db_geos, 6, area);
-            let key = format!(
-                "{}/{}",
-                std::net::Ipv4Addr::from(r.unwrap().net),
-                r.unwrap().len
-            );
-
-            if !nodes.contains_key(&ip) {
-                nodes.insert(ip, (sig.clone(), key));
-                if sig.contains('1') && ifaces.contains(&ip) {
-                    rtr2col.insert(ip, rtr2col.len() as u64);
-                }
-            }
-            if sig.contains('1') && ifaces.contains(&ip) && !t.contains(&ip) {
-                t.insert(ip);
-                row.push(*dst2row.get(&dst).unwrap());
-                col.push(*rtr2col.get(&ip).unwrap());
-            }
+    for ip in ips {
+        // the column of an interface is its router
+        let rtr = router(ctx, ip);
+        let is_col = mat.labels.entry(rtr).or_insert_with(|| label(ctx, rtr)).is_some();
+        if is_col && t.insert(rtr) {
+            let n = mat.rtr2col.len() as u64;
+            let c = *mat.rtr2col.entry(rtr).or_insert(n);
+            mat.row.push(dst_row);
+            mat.col.push(c);
         }
     }
+    Ok(())
 }
 
 // I/O helpers
-fn filetype(path_string: &str) -> &str {
-    if path_string.ends_with("warts")
-        || path_string.ends_with("warts.gz")
-        || path_string.ends_with("warts.g")
-    {
-        return "BIN";
+fn open_file(path: &Path) -> Box<dyn BufRead> {
+    match compress::open(path) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Error: {}: {}.", path.display(), e);
+            std::process::exit(1);
+        }
     }
-    return "TXT";
 }
 
-fn open_file(path: &std::path::PathBuf) -> Box<dyn std::io::Read + 'static> {
-    let input: Box<dyn std::io::Read + 'static> = if path.as_os_str() == "-" {
-        Box::new(std::io::stdin())
-    } else {
-        let path_string = path.as_os_str().to_str().unwrap().to_string();
-        let mut cmd;
-        if filetype(&path_string) == "BIN" {
-            cmd = format!("{} | sc_warts2text", path_string);
-        } else {
-            cmd = format!("{}", path_string);
-        }
-
-        if path_string.ends_with(".gz") || path_string.ends_with(".g") {
-            cmd = format!("gzip -dc {}", cmd);
-        } else {
-            cmd = format!("cat {}", cmd);
-        }
-        let mut child = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .stdout(Stdio::piped())
-            .spawn()
-            .unwrap();
-        let o = child.stdout.take().unwrap();
-        Box::new(o)
-    };
-
-    return input;
+// a rejected record, the run stops with --on-error fail or if it can't be quarantined
+fn reject_or_exit(rejects: &mut Rejects, e: &InputError) {
+    if let Err(e) = rejects.reject(e) {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    }
 }
 
 fn process(
-    read: &mut Box<dyn std::io::Read>,
-    area: &str,
-    ifaces: &HashSet<Ipv4Addr>,
-    geo_labeller: &IPLabeller<IPRange>,
-    row: &mut Vec<u64>,
-    col: &mut Vec<u64>,
-    dst2row: &mut HashMap<Ipv4Addr, u64>,
-    rtr2col: &mut HashMap<Ipv4Addr, u64>,
-    nodes: &mut HashMap<Ipv4Addr, (String, String)>,
-) {
-    let mut l = Link::new();
-    let mut link = Vec::new();
-    let mut node = HashMap::new();
-
-    let mut is_loop = false;
-    let mut prev_dest: Option<Ipv4Addr> = None;
-    let mut last: Vec<String> = Vec::new();
-
-    for line in BufReader::new(read).lines() {
-        let ll = line.unwrap();
-        let line = ll.trim();
-        let f: Vec<String> = line.split_whitespace().map(|x| x.to_string()).collect();
-        if &f[1] == "*" {
-            continue;
-        }
-        if f[0].chars().next().unwrap() == 't' {
-            if prev_dest.is_some() {
-                add_link(
-                    area,
-                    &link,
-                    prev_dest.unwrap().clone(),
-                    row,
-                    col,
-                    dst2row,
-                    rtr2col,
-                    nodes,
-                    &ifaces,
-                    geo_labeller,
-                );
+    traces: TraceReader,
+    ctx: &Context,
+    mat: &mut Matrix,
+    loops: &mut LoopStats,
+    rejects: &mut Rejects,
+) -> u64 {
+    // the IPv6 traces, skipped
+    let mut ipv6 = 0;
+    for trace in traces {
+        let trace = match trace {
+            Ok(t) => t,
+            Err(e) => {
+                reject_or_exit(rejects, &e);
+                continue;
             }
-            prev_dest = Some(f[4].parse().unwrap());
-            node.clear();
-            link.clear();
-            is_loop = false;
-        } else if last.len() > 1 && last[1] != "from" && f[1] != last[1] {
-            if node.contains_key(&f[1]) {
-                is_loop = true;
-            }
-            node.insert(f[1].to_string(), true);
-            l.io._in = last[1].to_string();
-            l.io.out = f[1].to_string();
-            if !is_loop {
-                link.push(l.clone());
+        };
+        let dst = match trace.dst {
+            IpAddr::V4(a) => a,
+            IpAddr::V6(_) => {
+                ipv6 += 1;
+                continue;
             }
+        };
+        let (mut link, is_loop) = extract(&trace);
+        ctx.loops.apply(&mut link, is_loop, loops);
+        if let Err(e) = add_link(ctx, &link, dst, mat) {
+            reject_or_exit(rejects, &e);
         }
-        last = f.clone();
     }
-
-    add_link(
-        area,
-        &link,
-        prev_dest.unwrap().clone(),
-        row,
-        col,
-        dst2row,
-        rtr2col,
-        nodes,
-        &ifaces,
-        geo_labeller,
-    );
+    ipv6
 }
 
 type Prefix4NoMeta<'a> = Prefix<u32, NoMeta>;
@@ -278,66 +465,307 @@ fn main() {
     };
 
     let mut pfxs: Vec<Prefix<u32, String>> = vec![];
-    let geo_labeller: IPLabeller<IPRange> = IPLabeller::new(&args.geo, &mut pfxs);
+    let geo_file = args.geo.display().to_string();
+    let geo_labeller: IPLabeller<IPRange> = match IPLabeller::new(&args.geo, &mut pfxs) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+
+    // the sidecar of dbmerge -m next to the db, if not given
+    let manifest = args.manifest.clone().or_else(|| {
+        let mut sidecar = args.geo.clone().into_os_string();
+        sidecar.push(".manifest");
+        Some(PathBuf::from(sidecar)).filter(|p| p.is_file())
+    });
+    let dbs = match &manifest {
+        Some(path) => DbManifest::read(path).unwrap_or_else(|e| {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }),
+        None => DbManifest::infer(geo_labeller.prefixes()).unwrap_or_else(|e| {
+            eprintln!("Error: {}: {}.", args.geo.display(), e);
+            std::process::exit(1);
+        }),
+    };
+    if let Err(e) = dbs.check(geo_labeller.prefixes()) {
+        eprintln!("Error: {}: {}.", args.geo.display(), e);
+        std::process::exit(1);
+    }
+
+    let mut as_pfxs: Vec<Prefix<u32, String>> = vec![];
+    let as_labeller: Option<IPLabeller<Pfx2As>> = args.bgp.as_ref().map(|b| match IPLabeller::new(b, &mut as_pfxs) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    });
+    if args.granularity == Granularity::Asn && as_labeller.is_none() {
+        eprintln!("Error: --row-granularity asn needs the routeviews file of -b.");
+        std::process::exit(1);
+    }
+    let mut as_set: HashSet<u32> = HashSet::new();
+    if let Some(asns) = &args.asns {
+        if as_labeller.is_none() {
+            eprintln!("Error: --asn needs the routeviews file of -b.");
+            std::process::exit(1);
+        }
+        for a in asns.split(',') {
+            match a.trim().trim_start_matches("AS").parse() {
+                Ok(a) => as_set.insert(a),
+                Err(_) => {
+                    eprintln!("Error: --asn: not an ASN: {}.", a);
+                    std::process::exit(1);
+                }
+            };
+        }
+    }
+
+    let area = match area_codes(&args.area) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("Error: -a: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    // -a as written to the headers, its codes and regions without spaces, e.g. CN,HK for "cn, hk"
+    let area_arg = args.area.split(',').map(|a| a.trim().to_uppercase()).join(",");
+    if args.min_votes == 0 || args.min_votes > dbs.len() {
+        eprintln!("Error: --min-votes must be between 1 and the {} databases.", dbs.len());
+        std::process::exit(1);
+    }
+
+    let aliases: Option<Aliases> = args.aliases.as_ref().map(|a| match Aliases::read(a) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    });
 
     let mut ifaces: HashSet<Ipv4Addr> = HashSet::new();
-    for l in BufReader::new(open_file(&PathBuf::from(&args.iface))).lines() {
-        ifaces.insert(l.unwrap().parse().unwrap());
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine).unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    let iface_name = args.iface.display().to_string();
+    let mut file = open_file(&args.iface);
+    let mut lineno = 0;
+    while let Some(r) = read_line(&mut file) {
+        lineno += 1;
+        let line = match r {
+            Ok(l) => l,
+            Err(e) => {
+                reject_or_exit(&mut rejects, &e.reject(Format::Addr, lineno, &iface_name));
+                match e {
+                    LineError::Utf8(_) => continue,
+                    LineError::Io(_) => break,
+                }
+            }
+        };
+        match line.trim().parse() {
+            Ok(ip) => {
+                ifaces.insert(ip);
+            }
+            Err(_) => reject_or_exit(
+                &mut rejects,
+                &InputError::new(Format::Addr, lineno, "not an IPv4 address")
+                    .in_file(&iface_name)
+                    .with_raw(&line),
+            ),
+        }
     }
 
-    let mut row: Vec<u64> = Vec::new();
-    let mut col: Vec<u64> = Vec::new();
-    let mut dst2row: HashMap<Ipv4Addr, u64> = HashMap::new();
-    let mut rtr2col: HashMap<Ipv4Addr, u64> = HashMap::new();
-    let mut nodes: HashMap<Ipv4Addr, (String, String)> = HashMap::new();
+    let ctx = Context {
+        area: &area,
+        min_votes: args.min_votes,
+        db_num: dbs.len(),
+        as_set: &as_set,
+        ifaces: &ifaces,
+        geo_labeller: &geo_labeller,
+        geo_file: &geo_file,
+        as_labeller: as_labeller.as_ref(),
+        aliases: aliases.as_ref(),
+        granularity: args.granularity,
+        loops: args.loops,
+    };
+    let mut mat = Matrix::default();
+    let mut loops = LoopStats::new(args.loops);
 
+    let mut ipv6 = 0;
     for input in inputs {
-        let mut file = open_file(&PathBuf::from(&input));
-        process(
-            &mut file,
-            args.area.to_str().unwrap(),
-            &ifaces,
-            &geo_labeller,
-            &mut row,
-            &mut col,
-            &mut dst2row,
-            &mut rtr2col,
-            &mut nodes,
-        );
+        let path = PathBuf::from(&input);
+        let traces = match TraceReader::open(&path) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Error: {}: {}.", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        ipv6 += process(traces, &ctx, &mut mat, &mut loops, &mut rejects);
+    }
+    let Matrix { row, col, dst2row, rtr2col, nodes, keys, labels } = mat;
+    loops.summary("trace2mat");
+    if ipv6 > 0 {
+        eprintln!("trace2mat: {} IPv6 traces skipped, the matrix is IPv4 only", ipv6);
+    }
+    if let Err(e) = rejects.summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
     }
 
-    let mut f = File::create("rows.csv").unwrap();
-    let mut row2ind: HashMap<u64, u64> = HashMap::new(); // keep track of original index used in row
-    for (i, k) in dst2row.keys().sorted().enumerate() {
-        let p = Prefix4NoMeta::new((*k).into(), 32);
-        let r = geo_labeller.match_pfx(&p);
-        let db_geos = r.unwrap().meta.as_ref().unwrap();
-        let sig = parse_sig(db_geos, 6, args.area.to_str().unwrap());
-        let key = format!(
-            "{}/{}",
-            std::net::Ipv4Addr::from(r.unwrap().net),
-            r.unwrap().len
-        );
-        let line = format!("{},{},{},{}\n", i, k, sig, key);
-        row2ind.insert(*dst2row.get(k).unwrap(), i as u64);
-        f.write_all(line.as_bytes()).unwrap();
+    if !args.outdir.as_os_str().is_empty() {
+        if let Err(e) = std::fs::create_dir_all(&args.outdir) {
+            eprintln!("Error: can't create {}: {}.", args.outdir.display(), e);
+            std::process::exit(1);
+        }
     }
+    // e.g. out/HK-rows.csv.zst with -o out -p HK- --compress=zstd
+    let output = |name: &str| {
+        let mut file = args.prefix.clone();
+        file.push(name);
+        file.push(args.codec.suffix());
+        args.outdir.join(file)
+    };
+    // an output is written whole or not at all, a failed write removes its
+    // temporary file before the exit
+    let write = |name: &str, fill: &mut dyn FnMut(&mut compress::Writer) -> io::Result<()>| {
+        let path = output(name);
+        let r = compress::create_atomic(&path, args.codec).and_then(|mut f| {
+            fill(&mut f)?;
+            f.finish()
+        });
+        if let Err(e) = r {
+            eprintln!("Error: can't write {}: {}.", path.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let grouped = args.granularity != Granularity::Ip;
+    let header = format!(
+        "#rows version=1 tool=trace2mat/{} columns=number,{},signature,key{}{} granularity={} area={} votes={} dbs={} names={}\n",
+        env!("CARGO_PKG_VERSION"),
+        if grouped { "group" } else { "ip" },
+        if as_labeller.is_some() { ",asn" } else { "" },
+        if grouped { ",dsts" } else { "" },
+        args.granularity,
+        area_arg,
+        args.min_votes,
+        dbs.len(),
+        dbs.names.join(",")
+    );
+    // the destinations of each row, lowest first
+    let mut groups: BTreeMap<RowKey, Vec<Ipv4Addr>> = BTreeMap::new();
+    for k in dst2row.keys().sorted() {
+        groups.entry(keys[k].clone()).or_default().push(*k);
+    }
+    let mut row2ind: HashMap<u64, u64> = HashMap::new(); // keep track of original index used in row
+    write("rows.csv", &mut |f| {
+        f.write_all(header.as_bytes())?;
+        for (i, (g, members)) in groups.iter().enumerate() {
+            let mut sig = vec!['0'; dbs.len()];
+            for k in members {
+                for (s, c) in sig.iter_mut().zip(nodes[k].0.chars()) {
+                    if c == '1' {
+                        *s = '1';
+                    }
+                }
+                row2ind.insert(*dst2row.get(k).unwrap(), i as u64);
+            }
+            let sig: String = sig.iter().collect();
+            let (_, key) = &nodes[&members[0]];
+            let mut line = format!("{},{},{},{}", i, g, sig, key);
+            if let Some(asn) = origin(as_labeller.as_ref(), members[0]) {
+                line += &format!(",{}", asn);
+            }
+            if grouped {
+                line += &format!(",{}", members.len());
+            }
+            line.push('\n');
+            f.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    });
 
-    f = File::create("mat.csv").unwrap();
     let mut col2ind: HashMap<u64, u64> = HashMap::new(); // keep track of appearance order of a router index (i.e. col)
     let ind_sorted = (0..row.len()).sorted_by_key(|x| row2ind.get(&row[*x]).unwrap());
+    // with aggregated rows or --counts, a destination counts once per router,
+    // however many of its traces traversed it, and each cell is written once;
+    // otherwise every (row, col) pair is written as found
+    let aggregate = grouped || args.counts;
+    let mut seen: HashSet<(u64, u64)> = HashSet::new();
+    let mut counts: HashMap<(u64, u64), u64> = HashMap::new();
+    let mut entries: Vec<(u64, u64)> = Vec::with_capacity(row.len());
     for i in ind_sorted {
+        if aggregate && !seen.insert((row[i], col[i])) {
+            continue;
+        }
         let ind = row2ind.get(&row[i]).unwrap();
         if !col2ind.contains_key(&col[i]) {
             col2ind.insert(col[i], col2ind.len() as u64);
         }
-        let line = format!("{},{}\n", ind, col2ind.get(&col[i]).unwrap());
-        f.write_all(line.as_bytes()).unwrap();
+        let cell = (*ind, *col2ind.get(&col[i]).unwrap());
+        let n = counts.entry(cell).or_insert(0);
+        if *n == 0 || !aggregate {
+            entries.push(cell);
+        }
+        *n += 1;
     }
 
-    f = File::create("cols.csv").unwrap();
-    for k in rtr2col.keys().sorted_by_key(|x| col2ind.get(rtr2col.get(x).unwrap()).unwrap()) {
-        let line = format!("{},{}\n", col2ind.get(rtr2col.get(k).unwrap()).unwrap(), k);
-        f.write_all(line.as_bytes()).unwrap();
+    match args.format {
+        MatFormat::Csv => write("mat.csv", &mut |f| {
+            for (r, c) in &entries {
+                let line = match args.counts {
+                    true => format!("{},{},{}\n", r, c, counts.get(&(*r, *c)).unwrap()),
+                    false => format!("{},{}\n", r, c),
+                };
+                f.write_all(line.as_bytes())?;
+            }
+            Ok(())
+        }),
+        MatFormat::Mtx => write("mat.mtx", &mut |f| {
+            let rows = output("rows.csv");
+            let cols = output("cols.csv");
+            let header = format!(
+                "%%MatrixMarket matrix coordinate {} general\n\
+                 % rows: {}, cols: {}, area: {}, granularity: {}\n\
+                 {} {} {}\n",
+                if args.counts { "integer" } else { "pattern" },
+                rows.file_name().unwrap().to_string_lossy(),
+                cols.file_name().unwrap().to_string_lossy(),
+                area_arg,
+                args.granularity,
+                groups.len(),
+                rtr2col.len(),
+                entries.len()
+            );
+            f.write_all(header.as_bytes())?;
+            for (r, c) in &entries {
+                let line = match args.counts {
+                    true => format!("{} {} {}\n", r + 1, c + 1, counts.get(&(*r, *c)).unwrap()),
+                    false => format!("{} {}\n", r + 1, c + 1),
+                };
+                f.write_all(line.as_bytes())?;
+            }
+            Ok(())
+        }),
     }
+
+    write("cols.csv", &mut |f| {
+        for k in rtr2col.keys().sorted_by_key(|x| col2ind.get(rtr2col.get(x).unwrap()).unwrap()) {
+            let mut line = format!("{},{}", col2ind.get(rtr2col.get(k).unwrap()).unwrap(), k);
+            let label = labels.get(k).unwrap().unwrap();
+            if let Some(asn) = origin(as_labeller.as_ref(), label) {
+                line += &format!(",{}", asn);
+            }
+            if let Some(a) = &aliases {
+                line += &format!(",{}", a.members(IpAddr::V4(*k)).iter().join(";"));
+            }
+            line.push('\n');
+            f.write_all(line.as_bytes())?;
+        }
+        Ok(())
+    });
 }
@@ -0,0 +1,178 @@
+// trace2path -- the ordered IP path of every trace of a batch of traceroute data files
+// =============================================================================
+// USAGE: see Usage below (./trace2path -h)
+// INPUT: traceroute data files from @ARGV, or STDIN, warts or warts2text,
+//        plain or gzip/zstd/xz/bz2 compressed, all detected by content
+//        binary path files of trace2path --format binary are detected too,
+//        e.g. to turn them into JSONL
+// OUTPUT: one record per trace to STDOUT, in the input order, see path::TracePath
+//         jsonl: {"monitor":..,"dst":..,"start":..,"stop":..,"hops":[{"ttl":1,"addr":..,"rtt":..},..]}
+//         with {"ttl":N,"star":true} for a TTL without a reply and the MPLS
+//         labels of a reply, if any
+//         binary: a compact big-endian encoding of the same records, see path::PathReader
+
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{Format, InputError, OnError, Rejects};
+use hitscanner::path::{PathReader, TracePath, PATH_MAGIC};
+use hitscanner::TraceReader;
+
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const HELP: &str = "\
+Usage: trace2path [OPTIONS] [files]
+
+When [files] is empty, read traces from STDIN
+OPTIONS:
+-h   print this help message
+--format      jsonl|binary output format, default jsonl
+--compress    none|gzip|zstd|xz|bz2 compression of the output, default none
+--on-error    skip|fail|quarantine malformed traces, default fail
+--quarantine  file of the quarantined lines, default trace2path.rejected
+EXAMPLE:
+    trace2path --format binary --compress zstd cycle-*.warts.gz > paths.bin.zst
+    trace2path paths.bin.zst | head
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PathFormat {
+    Jsonl,
+    Binary,
+}
+
+impl FromStr for PathFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" | "json" => Ok(PathFormat::Jsonl),
+            "binary" | "bin" => Ok(PathFormat::Binary),
+            _ => Err(format!("unknown format {}, use jsonl or binary", s)),
+        }
+    }
+}
+
+impl fmt::Display for PathFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PathFormat::Jsonl => write!(f, "jsonl"),
+            PathFormat::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+struct AppArgs {
+    format: PathFormat,
+    codec: Codec,
+    on_error: OnError,
+    quarantine: PathBuf,
+    inputs: Vec<std::ffi::OsString>,
+}
+
+fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
+    Ok(s.into())
+}
+
+fn getoption() -> Result<AppArgs, pico_args::Error> {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    // Help has a higher priority and should be handled separately.
+    if pargs.contains(["-h", "--help"]) {
+        print!("{}", HELP);
+        std::process::exit(0);
+    }
+
+    Ok(AppArgs {
+        format: pargs.opt_value_from_str("--format")?.unwrap_or(PathFormat::Jsonl),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("trace2path.rejected")),
+        inputs: pargs.finish(),
+    })
+}
+
+// the paths of an input, traces or a binary path file
+fn paths(path: &Path) -> std::io::Result<Box<dyn Iterator<Item = Result<TracePath, InputError>>>> {
+    let mut reader = compress::open(path)?;
+    let name = path.display().to_string();
+    if reader.fill_buf()?.starts_with(PATH_MAGIC) {
+        let paths = PathReader::new(reader)?;
+        return Ok(Box::new(paths.enumerate().map(move |(i, p)| {
+            p.map_err(|e| InputError::new(Format::Path, i + 1, &e).in_file(&name))
+        })));
+    }
+    let traces = TraceReader::new(reader);
+    Ok(Box::new(traces.map(move |t| match t {
+        Ok(t) => Ok(TracePath::new(&t)),
+        Err(e) => Err(e.in_file(&name)),
+    })))
+}
+
+fn main() {
+    let mut args = match getoption() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    if args.inputs.is_empty() {
+        args.inputs = vec![std::ffi::OsString::from("-")];
+    }
+
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine).unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    let stdout = PathBuf::from("-");
+    let mut out = match compress::create(&stdout, args.codec) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("Error: can't write -: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    let write_err = |e: std::io::Error| -> ! {
+        eprintln!("Error: can't write -: {}.", e);
+        std::process::exit(1);
+    };
+    if args.format == PathFormat::Binary {
+        out.write_all(PATH_MAGIC).unwrap_or_else(|e| write_err(e));
+    }
+    for input in &args.inputs {
+        let path = PathBuf::from(input);
+        let records = match paths(&path) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Error: {}: {}.", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        for record in records {
+            let p = match record {
+                Ok(p) => p,
+                Err(e) => {
+                    if let Err(e) = rejects.reject(&e) {
+                        eprintln!("Error: {}.", e);
+                        std::process::exit(1);
+                    }
+                    continue;
+                }
+            };
+            let r = match args.format {
+                PathFormat::Jsonl => writeln!(out, "{}", p.to_json()),
+                PathFormat::Binary => p.write_binary(&mut out),
+            };
+            r.unwrap_or_else(|e| write_err(e));
+        }
+    }
+    if let Err(e) = rejects.summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
+    }
+    out.finish().unwrap_or_else(|e| write_err(e));
+}
@@ -0,0 +1,247 @@
+// tracestats -- stop reasons and reachability of a batch of traceroute data files
+// =============================================================================
+// USAGE: see Usage below (./tracestats -h)
+// INPUT: traceroute data files from @ARGV, or STDIN, warts or warts2text,
+//        plain or gzip/zstd/xz/bz2 compressed, all detected by content
+// OUTPUT: CSV to STDOUT, a header line, then one line per monitor and one per
+//         destination prefix, sorted, with the columns
+//         kind,key,traces,completed,unreach,icmp,loop,gaplimit,hoplimit,other,
+//         completion,dst_replied,reply_rate,len_mean,len_p50,len_p90,len_max,lengths,dst_types
+//         kind: monitor or prefix, key: the monitor IP or the destination prefix
+//         completed...other: the traces per stop reason, other for none, error, gss and halted
+//         completion: completed / traces, reply_rate: dst_replied / traces
+//         len_*: the TTL of the last reply, lengths: its distribution, e.g. 8=3;9=12
+//         dst_types: the ICMP type/code of the destination replies, e.g. 0/0=10;3/3=2,
+//         ?=4 for the replies of sc_warts2text input, which has no reply types
+// NOTE:  sc_warts2text has no stop reason either, a text trace is completed if the
+//        destination replied, gaplimit if it ends in stars, other otherwise
+
+use hitscanner::compress::{self, Codec};
+use hitscanner::error::{OnError, Rejects};
+use hitscanner::iputils::host_mask;
+use hitscanner::trace::StopReason;
+use hitscanner::{Trace, TraceReader};
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+const HELP: &str = "\
+Usage: tracestats [OPTIONS] [files]
+
+When [files] is empty, read traces from STDIN
+OPTIONS:
+-h   print this help message
+--prefix4     the destination prefix length of IPv4, default 24
+--prefix6     the destination prefix length of IPv6, default 48
+--compress    none|gzip|zstd|xz|bz2 compression of the output, default none
+--on-error    skip|fail|quarantine malformed traces, default fail
+--quarantine  file of the quarantined lines, default tracestats.rejected
+EXAMPLE:
+    tracestats --prefix4 16 cycle-*.warts.gz > stats.csv
+";
+
+struct AppArgs {
+    prefix4: u8,
+    prefix6: u8,
+    codec: Codec,
+    on_error: OnError,
+    quarantine: PathBuf,
+    inputs: Vec<std::ffi::OsString>,
+}
+
+fn parse_path(s: &std::ffi::OsStr) -> Result<PathBuf, &'static str> {
+    Ok(s.into())
+}
+
+fn getoption() -> Result<AppArgs, pico_args::Error> {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    // Help has a higher priority and should be handled separately.
+    if pargs.contains(["-h", "--help"]) {
+        print!("{}", HELP);
+        std::process::exit(0);
+    }
+
+    Ok(AppArgs {
+        prefix4: pargs.opt_value_from_str("--prefix4")?.unwrap_or(24),
+        prefix6: pargs.opt_value_from_str("--prefix6")?.unwrap_or(48),
+        codec: pargs.opt_value_from_str("--compress")?.unwrap_or(Codec::Plain),
+        on_error: pargs.opt_value_from_str("--on-error")?.unwrap_or(OnError::Fail),
+        quarantine: pargs
+            .opt_value_from_os_str("--quarantine", parse_path)?
+            .unwrap_or(PathBuf::from("tracestats.rejected")),
+        inputs: pargs.finish(),
+    })
+}
+
+// the counters of a monitor or a destination prefix
+#[derive(Default)]
+struct Stats {
+    traces: u64,
+    stops: BTreeMap<StopReason, u64>,
+    replied: u64,
+    lengths: BTreeMap<u8, u64>,
+    dst_types: BTreeMap<Option<(u8, u8)>, u64>, // None for a text trace
+}
+
+impl Stats {
+    fn add(&mut self, trace: &Trace) {
+        self.traces += 1;
+        *self.stops.entry(trace.stop()).or_insert(0) += 1;
+        *self.lengths.entry(trace.length()).or_insert(0) += 1;
+        if let Some(h) = trace.dst_reply() {
+            self.replied += 1;
+            // only warts has a stop reason, and the reply types
+            let t = Some((h.icmp_type, h.icmp_code)).filter(|_| trace.stop_reason != 0);
+            *self.dst_types.entry(t).or_insert(0) += 1;
+        }
+    }
+
+    fn stop(&self, reason: StopReason) -> u64 {
+        self.stops.get(&reason).copied().unwrap_or(0)
+    }
+
+    // the length at rank q of all traces
+    fn length(&self, q: f64) -> u8 {
+        let rank = (q * (self.traces - 1) as f64).floor() as u64;
+        let mut seen = 0;
+        for (&len, &n) in &self.lengths {
+            seen += n;
+            if rank < seen {
+                return len;
+            }
+        }
+        0
+    }
+}
+
+const COLUMNS: &str = "kind,key,traces,completed,unreach,icmp,loop,gaplimit,hoplimit,other,\
+completion,dst_replied,reply_rate,len_mean,len_p50,len_p90,len_max,lengths,dst_types";
+
+fn write_stats(out: &mut compress::Writer, kind: &str, key: &str, s: &Stats) -> std::io::Result<()> {
+    let listed = [
+        StopReason::Completed,
+        StopReason::Unreach,
+        StopReason::Icmp,
+        StopReason::Loop,
+        StopReason::GapLimit,
+        StopReason::HopLimit,
+    ];
+    let other: u64 = s.stops.iter().filter(|(r, _)| !listed.contains(r)).map(|(_, n)| n).sum();
+    let sum: u64 = s.lengths.iter().map(|(&l, &n)| l as u64 * n).sum();
+    let lengths: Vec<String> = s.lengths.iter().map(|(l, n)| format!("{}={}", l, n)).collect();
+    let types: Vec<String> = s
+        .dst_types
+        .iter()
+        .map(|(t, n)| match t {
+            Some((t, c)) => format!("{}/{}={}", t, c, n),
+            None => format!("?={}", n),
+        })
+        .collect();
+    let traces = s.traces as f64;
+    write!(out, "{},{},{}", kind, key, s.traces)?;
+    for r in listed {
+        write!(out, ",{}", s.stop(r))?;
+    }
+    writeln!(
+        out,
+        ",{},{:.4},{},{:.4},{:.2},{},{},{},{},{}",
+        other,
+        s.stop(StopReason::Completed) as f64 / traces,
+        s.replied,
+        s.replied as f64 / traces,
+        sum as f64 / traces,
+        s.length(0.5),
+        s.length(0.9),
+        s.lengths.keys().max().unwrap(),
+        lengths.join(";"),
+        types.join(";")
+    )
+}
+
+// the destination prefix of a trace, e.g. 10.0.0.0/24
+fn prefix(ip: &IpAddr, prefix4: u8, prefix6: u8) -> String {
+    match ip {
+        IpAddr::V4(a) => {
+            let net = u32::from(*a) & !(host_mask(32 - prefix4) as u32);
+            format!("{}/{}", std::net::Ipv4Addr::from(net), prefix4)
+        }
+        IpAddr::V6(a) => {
+            let net = u128::from(*a) & !host_mask(128 - prefix6);
+            format!("{}/{}", std::net::Ipv6Addr::from(net), prefix6)
+        }
+    }
+}
+
+fn main() {
+    let mut args = match getoption() {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}.", e);
+            std::process::exit(1);
+        }
+    };
+    if args.prefix4 > 32 || args.prefix6 > 128 {
+        eprintln!("Error: --prefix4 must be at most 32 and --prefix6 at most 128.");
+        std::process::exit(1);
+    }
+    if args.inputs.is_empty() {
+        args.inputs = vec![std::ffi::OsString::from("-")];
+    }
+
+    let mut rejects = Rejects::new(args.on_error, &args.quarantine).unwrap_or_else(|e| {
+        eprintln!("Error: {}.", e);
+        std::process::exit(1);
+    });
+    let mut monitors: BTreeMap<IpAddr, Stats> = BTreeMap::new();
+    let mut prefixes: BTreeMap<(IpAddr, String), Stats> = BTreeMap::new();
+    for input in &args.inputs {
+        let path = PathBuf::from(input);
+        let traces = match TraceReader::open(&path) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Error: {}: {}.", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        for trace in traces {
+            let trace = match trace {
+                Ok(t) => t,
+                Err(e) => {
+                    if let Err(e) = rejects.reject(&e) {
+                        eprintln!("Error: {}.", e);
+                        std::process::exit(1);
+                    }
+                    continue;
+                }
+            };
+            monitors.entry(trace.src).or_default().add(&trace);
+            let p = prefix(&trace.dst, args.prefix4, args.prefix6);
+            // sorted by network address, not as text
+            let net: IpAddr = p.split('/').next().unwrap().parse().unwrap();
+            prefixes.entry((net, p)).or_default().add(&trace);
+        }
+    }
+    if let Err(e) = rejects.summary() {
+        eprintln!("Error: can't write {}: {}.", args.quarantine.display(), e);
+        std::process::exit(1);
+    }
+
+    let path = PathBuf::from("-");
+    let r = compress::create(&path, args.codec).and_then(|mut out| {
+        writeln!(out, "{}", COLUMNS)?;
+        for (m, s) in &monitors {
+            write_stats(&mut out, "monitor", &m.to_string(), s)?;
+        }
+        for ((_, p), s) in &prefixes {
+            write_stats(&mut out, "prefix", p, s)?;
+        }
+        out.finish()
+    });
+    if let Err(e) = r {
+        eprintln!("Error: can't write -: {}.", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,809 @@
+// Reader for scamper's warts traceroute files, replaces the sc_warts2text pipe
+//   - WartsReader: iterator over the objects of a binary warts stream
+//     - list, cycle start/def/stop and trace (with its hops) are decoded
+//     - other object types (ping, tracelb, ...) are skipped
+//   - TextReader: iterator over the traces of sc_warts2text output
+// The binary layout follows scamper/scamper_file_warts.c (cvs-20230224):
+//   object header: uint16 magic (0x1205), uint16 type, uint32 length
+//   all integers are big-endian, strings are NUL terminated
+
+use std::io::{BufRead, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::{read_line, Format, InputError, LineError};
+use crate::trace::{Hop, MplsLabel, Trace};
+
+// errors are plain messages here, given a position by the readers
+type Result<T> = std::result::Result<T, String>;
+
+pub const WARTS_MAGIC: u16 = 0x1205;
+
+const WARTS_TYPE_LIST: u16 = 1;
+const WARTS_TYPE_CYCLE_START: u16 = 2;
+const WARTS_TYPE_CYCLE_DEF: u16 = 3;
+const WARTS_TYPE_CYCLE_STOP: u16 = 4;
+const WARTS_TYPE_TRACE: u16 = 6;
+
+// the longest object read, far above any scamper object, so a corrupt
+// length can't allocate gigabytes
+const WARTS_MAX_OBJECT: usize = 16 << 20;
+
+const WARTS_ADDR_IPV4: u8 = 1;
+const WARTS_ADDR_IPV6: u8 = 2;
+
+// ICMP extension class and type of an MPLS label stack (RFC 4950)
+const ICMPEXT_MPLS: (u8, u8) = (1, 1);
+
+#[derive(Debug, Clone)]
+pub struct List {
+    pub id: u32,
+    pub list_id: u32,
+    pub name: String,
+    pub descr: Option<String>,
+    pub monitor: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cycle {
+    pub id: u32,
+    pub list_id: u32,
+    pub cycle_id: u32,
+    pub start: u32,
+    pub stop: Option<u32>,
+    pub hostname: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CycleStop {
+    pub id: u32,
+    pub stop: u32,
+}
+
+#[derive(Debug, Clone)]
+pub enum Record {
+    List(List),
+    CycleStart(Cycle),
+    CycleDef(Cycle),
+    CycleStop(CycleStop),
+    Trace(Trace),
+}
+
+fn invalid(msg: &str) -> String {
+    msg.to_string()
+}
+
+// cursor over the body of a single warts object
+struct Cursor<'a> {
+    buf: &'a [u8],
+    off: usize,
+    // addresses embedded in this object, referred to later by index
+    addrs: Vec<IpAddr>,
+}
+
+// the flags preceding a parameter block, flag ids start from 1
+// and every byte holds 7 of them, the high bit marks a following byte
+struct Flags {
+    bytes: Vec<u8>,
+    end: usize, // offset right after the parameter block
+}
+
+impl Flags {
+    fn isset(&self, id: usize) -> bool {
+        let (i, b) = ((id - 1) / 7, (id - 1) % 7);
+        i < self.bytes.len() && self.bytes[i] & (1 << b) != 0
+    }
+
+    fn max_id(&self) -> usize {
+        self.bytes.len() * 7
+    }
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Cursor {
+            buf,
+            off: 0,
+            addrs: Vec::new(),
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.off + n > self.buf.len() {
+            return Err(invalid("truncated warts object"));
+        }
+        let b = &self.buf[self.off..self.off + n];
+        self.off += n;
+        Ok(b)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let rest = &self.buf[self.off..];
+        let n = match rest.iter().position(|&c| c == 0) {
+            Some(n) => n,
+            None => return Err(invalid("unterminated string in warts object")),
+        };
+        let s = String::from_utf8_lossy(&rest[..n]).to_string();
+        self.off += n + 1;
+        Ok(s)
+    }
+
+    // timeval: uint32 seconds, uint32 microseconds; only seconds are kept
+    fn timeval(&mut self) -> Result<u32> {
+        let sec = self.u32()?;
+        self.u32()?;
+        Ok(sec)
+    }
+
+    // address: uint8 length, then either uint8 type + address bytes,
+    // or, if the length is 0, uint32 index of an address seen before
+    fn addr(&mut self) -> Result<IpAddr> {
+        let len = self.u8()? as usize;
+        if len == 0 {
+            let id = self.u32()? as usize;
+            return match self.addrs.get(id) {
+                Some(a) => Ok(*a),
+                None => Err(invalid("reference to unknown address in warts object")),
+            };
+        }
+        let t = self.u8()?;
+        let b = self.bytes(len)?;
+        let a = match (t, len) {
+            (WARTS_ADDR_IPV4, 4) => IpAddr::V4(Ipv4Addr::new(b[0], b[1], b[2], b[3])),
+            (WARTS_ADDR_IPV6, 16) => {
+                let mut o = [0u8; 16];
+                o.copy_from_slice(b);
+                IpAddr::V6(Ipv6Addr::from(o))
+            }
+            _ => return Err(invalid("unsupported address type in warts object")),
+        };
+        self.addrs.push(a);
+        Ok(a)
+    }
+
+    // icmp extensions: uint16 total length, then the extensions, each
+    // uint16 data length, uint8 class number, uint8 class type and the data;
+    // only the MPLS label stack is kept
+    fn icmpext(&mut self) -> Result<Vec<MplsLabel>> {
+        let end = self.u16()? as usize + self.off;
+        let mut mpls = Vec::new();
+        while self.off < end {
+            let len = self.u16()? as usize;
+            let class = (self.u8()?, self.u8()?);
+            let data = self.bytes(len)?;
+            if class == ICMPEXT_MPLS {
+                for e in data.chunks_exact(4) {
+                    mpls.push(MplsLabel::from_u32(u32::from_be_bytes([e[0], e[1], e[2], e[3]])));
+                }
+            }
+        }
+        if self.off != end {
+            return Err(invalid("malformed icmp extensions in warts hop"));
+        }
+        Ok(mpls)
+    }
+
+    fn flags(&mut self) -> Result<Flags> {
+        let mut bytes = Vec::new();
+        loop {
+            let b = self.u8()?;
+            bytes.push(b & 0x7F);
+            if b & 0x80 == 0 {
+                break;
+            }
+        }
+        // no parameter length is written when no flag is set
+        if bytes.len() == 1 && bytes[0] == 0 {
+            return Ok(Flags {
+                bytes,
+                end: self.off,
+            });
+        }
+        let len = self.u16()? as usize;
+        Ok(Flags {
+            bytes,
+            end: self.off + len,
+        })
+    }
+
+    fn skip_to(&mut self, end: usize) -> Result<()> {
+        if end > self.buf.len() {
+            return Err(invalid("truncated warts object"));
+        }
+        self.off = end;
+        Ok(())
+    }
+}
+
+fn read_list(c: &mut Cursor) -> Result<List> {
+    let mut l = List {
+        id: c.u32()?,
+        list_id: c.u32()?,
+        name: c.string()?,
+        descr: None,
+        monitor: None,
+    };
+    let flags = c.flags()?;
+    if flags.isset(1) {
+        l.descr = Some(c.string()?);
+    }
+    if flags.isset(2) {
+        l.monitor = Some(c.string()?);
+    }
+    c.skip_to(flags.end)?;
+    Ok(l)
+}
+
+fn read_cycle(c: &mut Cursor) -> Result<Cycle> {
+    let mut cycle = Cycle {
+        id: c.u32()?,
+        list_id: c.u32()?,
+        cycle_id: c.u32()?,
+        start: c.u32()?,
+        stop: None,
+        hostname: None,
+    };
+    let flags = c.flags()?;
+    if flags.isset(1) {
+        cycle.stop = Some(c.u32()?);
+    }
+    if flags.isset(2) {
+        cycle.hostname = Some(c.string()?);
+    }
+    c.skip_to(flags.end)?;
+    Ok(cycle)
+}
+
+fn read_cycle_stop(c: &mut Cursor) -> Result<CycleStop> {
+    let stop = CycleStop {
+        id: c.u32()?,
+        stop: c.u32()?,
+    };
+    let flags = c.flags()?;
+    c.skip_to(flags.end)?;
+    Ok(stop)
+}
+
+fn read_hop(c: &mut Cursor) -> Result<Hop> {
+    let mut addr = None;
+    let mut hop = Hop::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0, 0.0);
+    let flags = c.flags()?;
+    for id in 1..=flags.max_id() {
+        if !flags.isset(id) {
+            continue;
+        }
+        match id {
+            1 => return Err(invalid("global address ids are not supported")),
+            2 => hop.probe_ttl = c.u8()?,
+            3 => hop.reply_ttl = c.u8()?,
+            4 => c.skip(1)?, // hop flags
+            5 => hop.probe_id = c.u8()?,
+            6 => hop.rtt = c.u32()? as f64 / 1000.0,
+            7 => {
+                let tc = c.u16()?;
+                hop.icmp_type = (tc >> 8) as u8;
+                hop.icmp_code = (tc & 0xFF) as u8;
+            }
+            8..=10 => c.skip(2)?, // probe size, reply size, reply ipid
+            11 => c.skip(1)?,      // reply tos
+            12 | 13 => c.skip(2)?, // next-hop mtu, quoted ip length
+            14 => hop.quoted_ttl = c.u8()?,
+            15 | 16 => c.skip(1)?, // tcp flags, quoted tos
+            17 => hop.mpls = c.icmpext()?,
+            18 => addr = Some(c.addr()?),
+            _ => break, // not needed, skipped below
+        }
+    }
+    c.skip_to(flags.end)?;
+    match addr {
+        Some(a) => hop.addr = a,
+        None => return Err(invalid("hop without address in warts trace")),
+    }
+    Ok(hop)
+}
+
+fn read_trace(c: &mut Cursor) -> Result<Trace> {
+    let (mut src, mut dst) = (None, None);
+    let mut t = Trace::default();
+    let flags = c.flags()?;
+    for id in 1..=flags.max_id() {
+        if !flags.isset(id) {
+            continue;
+        }
+        match id {
+            1 => t.list_id = c.u32()?,
+            2 => t.cycle_id = c.u32()?,
+            3 | 4 => return Err(invalid("global address ids are not supported")),
+            5 => t.start = c.timeval()?,
+            6 => t.stop_reason = c.u8()?,
+            7 => t.stop_data = c.u8()?,
+            8..=11 => c.skip(1)?, // flags, attempts, hoplimit, type
+            12..=14 => c.skip(2)?, // probe size, source port, destination port
+            15 => t.firsthop = c.u8()?,
+            16..=18 => c.skip(1)?, // tos, wait, loops
+            19 => t.hop_count = c.u16()?,
+            20..=22 => c.skip(1)?, // gaplimit, gapaction, loopaction
+            23 => c.skip(2)?,     // probe count
+            24 | 25 => c.skip(1)?, // waitprobe, confidence
+            26 => src = Some(c.addr()?),
+            27 => dst = Some(c.addr()?),
+            _ => break, // not needed, skipped below
+        }
+    }
+    c.skip_to(flags.end)?;
+    match (src, dst) {
+        (Some(s), Some(d)) => {
+            t.src = s;
+            t.dst = d;
+        }
+        _ => return Err(invalid("trace without source or destination address")),
+    }
+
+    let n = c.u16()?;
+    for _ in 0..n {
+        t.hops.push(read_hop(c)?);
+    }
+    // the trailer (pmtud, lastditch, doubletree) is not needed
+    Ok(t)
+}
+
+pub struct WartsReader<R: Read> {
+    read: R,
+    object: usize, // number of objects read
+    done: bool,    // the stream can't be followed after a broken object header
+}
+
+impl<R: Read> WartsReader<R> {
+    pub fn new(read: R) -> Self {
+        WartsReader {
+            read,
+            object: 0,
+            done: false,
+        }
+    }
+
+    // only the trace objects of the stream
+    pub fn traces(self) -> impl Iterator<Item = std::result::Result<Trace, InputError>> {
+        self.filter_map(|r| match r {
+            Ok(Record::Trace(t)) => Some(Ok(t)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    // Ok(None) on a clean end of file
+    fn read_object(&mut self) -> Result<Option<(u16, Vec<u8>)>> {
+        let mut hdr = [0u8; 8];
+        let mut n = 0;
+        while n < hdr.len() {
+            let r = self.read.read(&mut hdr[n..]).map_err(|e| e.to_string())?;
+            if r == 0 {
+                if n == 0 {
+                    return Ok(None);
+                }
+                return Err(invalid("truncated warts object header"));
+            }
+            n += r;
+        }
+        if u16::from_be_bytes([hdr[0], hdr[1]]) != WARTS_MAGIC {
+            return Err(invalid("bad warts magic"));
+        }
+        let t = u16::from_be_bytes([hdr[2], hdr[3]]);
+        let len = u32::from_be_bytes([hdr[4], hdr[5], hdr[6], hdr[7]]) as usize;
+        if len > WARTS_MAX_OBJECT {
+            return Err(invalid("object too large"));
+        }
+        // the buffer grows with the bytes read, up to len
+        let mut buf = Vec::new();
+        (&mut self.read).take(len as u64).read_to_end(&mut buf).map_err(|e| e.to_string())?;
+        if buf.len() < len {
+            return Err(invalid("truncated warts object"));
+        }
+        Ok(Some((t, buf)))
+    }
+}
+
+impl<R: Read> Iterator for WartsReader<R> {
+    type Item = std::result::Result<Record, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.object += 1;
+            let (t, buf) = match self.read_object() {
+                Ok(Some(o)) => o,
+                Ok(None) => return None,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(InputError::new(Format::Warts, self.object, &e)));
+                }
+            };
+            let mut c = Cursor::new(&buf);
+            let r = match t {
+                WARTS_TYPE_LIST => read_list(&mut c).map(Record::List),
+                WARTS_TYPE_CYCLE_START => read_cycle(&mut c).map(Record::CycleStart),
+                WARTS_TYPE_CYCLE_DEF => read_cycle(&mut c).map(Record::CycleDef),
+                WARTS_TYPE_CYCLE_STOP => read_cycle_stop(&mut c).map(Record::CycleStop),
+                WARTS_TYPE_TRACE => read_trace(&mut c).map(Record::Trace),
+                _ => continue,
+            };
+            return Some(r.map_err(|e| InputError::new(Format::Warts, self.object, &e)));
+        }
+        None
+    }
+}
+
+// sc_warts2text output, with the start timestamp appended to the header:
+//   traceroute from 1.2.3.4 to 5.6.7.8 1677196800
+//    1  10.0.0.1  0.512 ms
+//    2  *
+//    3  10.0.0.3  2.048 ms
+//        MPLS Label 16005 TC 0 S 1 TTL 1
+// the quoted TTL is not in the text, see mpls::tunnel
+pub struct TextReader<R: BufRead> {
+    read: R,
+    line: usize, // number of lines read
+    done: bool,  // the input can't be read any further
+    trace: Option<Trace>,
+    pending: Option<InputError>,
+}
+
+impl<R: BufRead> TextReader<R> {
+    pub fn new(read: R) -> Self {
+        TextReader {
+            read,
+            line: 0,
+            done: false,
+            trace: None,
+            pending: None,
+        }
+    }
+}
+
+fn parse_header(f: &[&str]) -> Result<Trace> {
+    if f.len() < 5 {
+        return Err(invalid("malformed traceroute header"));
+    }
+    let addr = |s: &str| s.parse::<IpAddr>().map_err(|_| invalid("malformed address"));
+    let start = match f.get(5) {
+        Some(s) => s.parse().map_err(|_| invalid("malformed start time"))?,
+        None => 0,
+    };
+    Ok(Trace::new(addr(f[2])?, addr(f[4])?, start))
+}
+
+fn parse_hop(f: &[&str]) -> Result<Option<Hop>> {
+    if f.len() < 2 {
+        return Err(invalid("malformed hop line"));
+    }
+    let ttl: u8 = f[0].parse().map_err(|_| invalid("malformed hop ttl"))?;
+    if f[1] == "*" {
+        return Ok(None);
+    }
+    let addr = f[1].parse().map_err(|_| invalid("malformed hop address"))?;
+    let rtt = match f.get(2) {
+        Some(s) => s.parse().map_err(|_| invalid("malformed hop rtt"))?,
+        None => return Err(invalid("malformed hop line")),
+    };
+    Ok(Some(Hop::new(addr, ttl, rtt)))
+}
+
+impl<R: BufRead> Iterator for TextReader<R> {
+    type Item = std::result::Result<Trace, InputError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.pending.take() {
+            return Some(Err(e));
+        }
+        if self.done {
+            return None;
+        }
+        while let Some(line) = read_line(&mut self.read) {
+            self.line += 1;
+            let err = |e: String, raw: &str| InputError::new(Format::WartsText, self.line, &e).with_raw(raw);
+            let line = match line {
+                Ok(l) => l,
+                // the trace being read is incomplete, and dropped
+                Err(e @ LineError::Io(_)) => {
+                    self.done = true;
+                    self.trace = None;
+                    return Some(Err(e.reject(Format::WartsText, self.line, "-")));
+                }
+                Err(e) => return Some(Err(e.reject(Format::WartsText, self.line, "-"))),
+            };
+            let f: Vec<&str> = line.split_whitespace().collect();
+            if f.is_empty() {
+                continue;
+            }
+            if f[0] == "traceroute" {
+                match parse_header(&f) {
+                    Ok(t) => {
+                        if let Some(prev) = self.trace.replace(t) {
+                            return Some(Ok(prev));
+                        }
+                    }
+                    // the hops that follow are dropped along with the header
+                    Err(e) => match self.trace.take() {
+                        Some(prev) => {
+                            self.pending = Some(err(e, &line));
+                            return Some(Ok(prev));
+                        }
+                        None => return Some(Err(err(e, &line))),
+                    },
+                }
+                continue;
+            }
+            // a label stack entry of the hop before
+            if f[0] == "MPLS" {
+                let label = match line.parse::<MplsLabel>() {
+                    Ok(m) => m,
+                    Err(e) => return Some(Err(err(e, &line))),
+                };
+                if let Some(h) = self.trace.as_mut().and_then(|t| t.hops.last_mut()) {
+                    h.mpls.push(label);
+                }
+                continue;
+            }
+            let hop = match parse_hop(&f) {
+                Ok(h) => h,
+                Err(e) => return Some(Err(err(e, &line))),
+            };
+            if let Some(t) = self.trace.as_mut() {
+                t.hop_count = t.hop_count.max(f[0].parse().unwrap_or(0));
+                if let Some(h) = hop {
+                    t.hops.push(h);
+                }
+            }
+        }
+        self.trace.take().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an object: magic, type, length and body
+    fn object(t: u16, body: &[u8]) -> Vec<u8> {
+        let mut o = Vec::new();
+        o.extend(WARTS_MAGIC.to_be_bytes());
+        o.extend(t.to_be_bytes());
+        o.extend((body.len() as u32).to_be_bytes());
+        o.extend(body);
+        o
+    }
+
+    // a parameter block of (flag id, value), ids ascending
+    fn params(p: &[(usize, Vec<u8>)]) -> Vec<u8> {
+        if p.is_empty() {
+            return vec![0];
+        }
+        let max = p.iter().map(|(id, _)| *id).max().unwrap();
+        let mut flags = vec![0u8; (max - 1) / 7 + 1];
+        for (id, _) in p {
+            flags[(id - 1) / 7] |= 1 << ((id - 1) % 7);
+        }
+        let n = flags.len();
+        flags[..n - 1].iter_mut().for_each(|f| *f |= 0x80);
+        let values: Vec<u8> = p.iter().flat_map(|(_, v)| v.clone()).collect();
+        flags.extend((values.len() as u16).to_be_bytes());
+        flags.extend(values);
+        flags
+    }
+
+    fn addr4(a: [u8; 4]) -> Vec<u8> {
+        let mut v = vec![4, WARTS_ADDR_IPV4];
+        v.extend(a);
+        v
+    }
+
+    // an address embedded before in the same object
+    fn addr_ref(id: u32) -> Vec<u8> {
+        let mut v = vec![0];
+        v.extend(id.to_be_bytes());
+        v
+    }
+
+    // icmp extensions, each (class, type, data)
+    fn icmpext(exts: &[(u8, u8, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (class, t, data) in exts {
+            body.extend((data.len() as u16).to_be_bytes());
+            body.extend([*class, *t]);
+            body.extend(data);
+        }
+        let mut v = (body.len() as u16).to_be_bytes().to_vec();
+        v.extend(body);
+        v
+    }
+
+    fn lse(label: u32, tc: u32, s: bool, ttl: u32) -> Vec<u8> {
+        ((label << 12) | (tc << 9) | ((s as u32) << 8) | ttl).to_be_bytes().to_vec()
+    }
+
+    // 192.0.2.1 to 10.0.0.3, hops are parameter blocks
+    fn trace(hops: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = params(&[
+            (5, [1677196800u32.to_be_bytes(), 0u32.to_be_bytes()].concat()),
+            (6, vec![1]), // completed
+            (19, 3u16.to_be_bytes().to_vec()),
+            (26, addr4([192, 0, 2, 1])),
+            (27, addr4([10, 0, 0, 3])),
+        ]);
+        body.extend((hops.len() as u16).to_be_bytes());
+        for h in hops {
+            body.extend(h);
+        }
+        body.extend([0, 0]);
+        object(WARTS_TYPE_TRACE, &body)
+    }
+
+    fn hop(ttl: u8, addr: Vec<u8>, icmp: u16, ext: Option<Vec<u8>>) -> Vec<u8> {
+        let mut p = vec![
+            (2, vec![ttl]),
+            (6, (ttl as u32 * 1000).to_be_bytes().to_vec()),
+            (7, icmp.to_be_bytes().to_vec()),
+        ];
+        if let Some(e) = ext {
+            p.push((17, e));
+        }
+        p.push((18, addr));
+        params(&p)
+    }
+
+    // an input that can't be read, like a corrupt compressed stream
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "corrupt stream"))
+        }
+    }
+
+    fn traces(bytes: Vec<u8>) -> Vec<std::result::Result<Trace, InputError>> {
+        WartsReader::new(std::io::Cursor::new(bytes)).traces().collect()
+    }
+
+    #[test]
+    fn mpls_label_stack_of_a_hop() {
+        let stack = [lse(16005, 0, false, 1), lse(24, 5, true, 254)].concat();
+        let ext = icmpext(&[(2, 1, vec![0; 4]), (1, 1, stack)]);
+        let bytes = trace(&[
+            hop(1, addr4([10, 0, 0, 1]), 11 << 8, None),
+            hop(2, addr4([10, 0, 0, 2]), 11 << 8, Some(ext)),
+            hop(3, addr4([10, 0, 0, 3]), 3 << 8 | 3, None),
+        ]);
+        let t = traces(bytes).pop().unwrap().unwrap();
+        assert_eq!((t.src.to_string(), t.dst.to_string()), ("192.0.2.1".into(), "10.0.0.3".into()));
+        assert_eq!((t.start, t.stop_reason, t.hop_count), (1677196800, 1, 3));
+        assert_eq!(t.hops.len(), 3);
+        assert!(t.hops[0].mpls.is_empty());
+        // the other extension is skipped
+        let mpls = &t.hops[1].mpls;
+        assert_eq!(mpls.len(), 2);
+        assert_eq!((mpls[0].label, mpls[0].tc, mpls[0].s, mpls[0].ttl), (16005, 0, false, 1));
+        assert_eq!((mpls[1].label, mpls[1].tc, mpls[1].s, mpls[1].ttl), (24, 5, true, 254));
+        assert_eq!(mpls[0].to_string(), "MPLS Label 16005 TC 0 S 0 TTL 1");
+        let dst = t.dst_reply().unwrap();
+        assert_eq!((dst.icmp_type, dst.icmp_code, dst.rtt), (3, 3, 3.0));
+    }
+
+    #[test]
+    fn address_references() {
+        // the hop of the destination refers to it by its index, 1 after the source
+        let bytes = trace(&[hop(1, addr4([10, 0, 0, 1]), 11 << 8, None), hop(2, addr_ref(1), 3 << 8 | 3, None)]);
+        let t = traces(bytes).pop().unwrap().unwrap();
+        assert_eq!(t.hops[1].addr, t.dst);
+        let bytes = trace(&[hop(1, addr_ref(7), 11 << 8, None)]);
+        let e = traces(bytes).pop().unwrap().unwrap_err();
+        assert_eq!(e.msg, "reference to unknown address in warts object");
+    }
+
+    #[test]
+    fn malformed_icmp_extensions() {
+        // the extension claims more data than the total length
+        let mut ext = icmpext(&[(1, 1, lse(16005, 0, true, 1))]);
+        ext[1] -= 2;
+        let bytes = trace(&[hop(1, addr4([10, 0, 0, 1]), 11 << 8, Some(ext))]);
+        let e = traces(bytes).pop().unwrap().unwrap_err();
+        assert_eq!((e.format, e.line), (Format::Warts, 1));
+        assert_eq!(e.msg, "malformed icmp extensions in warts hop");
+        // past the end of the object
+        let mut ext = icmpext(&[(1, 1, lse(16005, 0, true, 1))]);
+        ext[3] += 100;
+        let bytes = trace(&[hop(1, addr4([10, 0, 0, 1]), 11 << 8, Some(ext))]);
+        let e = traces(bytes).pop().unwrap().unwrap_err();
+        assert_eq!(e.msg, "truncated warts object");
+    }
+
+    #[test]
+    fn stream_errors() {
+        let good = trace(&[hop(1, addr4([10, 0, 0, 3]), 3 << 8 | 3, None)]);
+        // other object types are skipped, a bad object doesn't stop the stream
+        let mut bytes = object(9, &[1, 2, 3]);
+        bytes.extend(object(WARTS_TYPE_TRACE, &[0x80]));
+        bytes.extend(&good);
+        let r = traces(bytes);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[0].as_ref().unwrap_err().line, 2);
+        assert!(r[1].is_ok());
+        // a broken object header does
+        let mut bytes = good.clone();
+        bytes.extend([0x12, 0x06, 0, 6]);
+        bytes.extend(&good);
+        let r = traces(bytes);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[1].as_ref().unwrap_err().msg, "bad warts magic");
+        let mut bytes = good.clone();
+        bytes.extend(&good[..5]);
+        assert_eq!(traces(bytes)[1].as_ref().unwrap_err().msg, "truncated warts object header");
+        let mut bytes = good.clone();
+        bytes.extend(&good[..good.len() - 1]);
+        assert_eq!(traces(bytes)[1].as_ref().unwrap_err().msg, "truncated warts object");
+        // a corrupt length stops the stream before its object is read
+        let mut bytes = good.clone();
+        bytes.extend([0x12, 0x05, 0, 6, 0xff, 0xff, 0xff, 0xff]);
+        bytes.extend(&good);
+        let r = traces(bytes);
+        assert_eq!(r.len(), 2);
+        assert_eq!(r[1].as_ref().unwrap_err().msg, "object too large");
+    }
+
+    #[test]
+    fn text_traces() {
+        let text = "\
+traceroute from 192.0.2.1 to 10.0.0.3 1677196800
+ 1  10.0.0.1  1.000 ms
+ 2  *
+ 3  10.0.0.2  2.000 ms
+     MPLS Label 16005 TC 0 S 1 TTL 1
+traceroute from 192.0.2.1 to nowhere 1677196800
+ 1  10.0.0.9  1.000 ms
+traceroute from 192.0.2.1 to 10.0.0.4 1677196900
+ 1  10.0.0.1  x ms
+ 4  10.0.0.4  4.000 ms
+";
+        let r: Vec<_> = TextReader::new(text.as_bytes()).collect();
+        assert_eq!(r.len(), 4);
+        let t = r[0].as_ref().unwrap();
+        assert_eq!((t.hops.len(), t.hop_count), (2, 3));
+        assert_eq!(t.hops[1].mpls[0].label, 16005);
+        let e = r[1].as_ref().unwrap_err();
+        assert_eq!((e.format, e.line, e.msg.as_str()), (Format::WartsText, 6, "malformed address"));
+        assert_eq!(e.raw.as_deref(), Some("traceroute from 192.0.2.1 to nowhere 1677196800"));
+        assert_eq!(r[2].as_ref().unwrap_err().msg, "malformed hop rtt");
+        let t = r[3].as_ref().unwrap();
+        assert_eq!((t.start, t.hops.len(), t.hop_count), (1677196900, 1, 4));
+    }
+
+    #[test]
+    fn unreadable_text() {
+        let text = b"traceroute from 192.0.2.1 to 10.0.0.3 1677196800\n 1  10.0.0.\xff  1.000 ms\n 2  10.0.0.3  2.000 ms\n";
+        let r: Vec<_> = TextReader::new(&text[..]).collect();
+        assert_eq!(r.len(), 2);
+        let e = r[0].as_ref().unwrap_err();
+        assert_eq!((e.line, e.msg.as_str()), (2, "not UTF-8"));
+        assert_eq!(r[1].as_ref().unwrap().hops.len(), 1);
+        // an input that fails halfway ends the traces, the one being read included
+        let text = b"traceroute from 192.0.2.1 to 10.0.0.3 1677196800\n 1  10.0.0.1  1.000 ms\n";
+        let r: Vec<_> = TextReader::new(std::io::BufReader::new(text.chain(Broken))).collect();
+        assert_eq!(r.len(), 1, "{:?}", r);
+        assert_eq!(r[0].as_ref().unwrap_err().msg, "corrupt stream");
+    }
+}
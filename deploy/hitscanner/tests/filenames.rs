@@ -0,0 +1,254 @@
+// Adversarial file names: the binaries must open them as they are, without a
+// shell, so quotes, spaces and $(...) in a path neither break the run nor run
+// any command. Every test works in a scratch directory whose own name is also
+// hostile, and checks that the injected `touch pwned` never happened.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+const TRACE: &str = "\
+traceroute from 192.0.2.1 to 10.0.0.3 1677196800
+ 1  10.0.0.1  1.000 ms
+ 2  10.0.0.2  2.000 ms
+ 3  10.0.0.3  3.000 ms
+";
+
+const LINKS: &str = "\
+10.0.0.1 10.0.0.2 N 0 0.500 1 1 192.0.2.1 1677196800 1677196800
+10.0.0.2 10.0.0.3 Y 0 0.500 1 2 192.0.2.1 1677196800 1677196800
+";
+
+// the #links header of LINKS written by `tool`, with the loop counters of trace2link
+fn header(tool: &str) -> String {
+    format!(
+        "#links version=1 tool={}/{} \
+         columns=in,out,is_dest,star,delay,freq,ttl,monitor,firstseen,lastseen \
+         monitors=192.0.2.1 window=1677196800-1677196800 delay=min{}\n",
+        tool,
+        env!("CARGO_PKG_VERSION"),
+        if tool == "trace2link" {
+            " loop_policy=drop traces=1 looping=0 loop_links=0"
+        } else {
+            ""
+        }
+    )
+}
+
+const DB: &str = "\
+0,167772159,AU
+167772160,4294967295,CN
+";
+
+// DB after dbmerge
+const MERGED: &str = "\
+0,167772159,0,AU
+167772160,4294967295,0,CN
+";
+
+fn names() -> Vec<OsString> {
+    let mut names: Vec<OsString> = [
+        "with space",
+        "semi;colon",
+        "$(touch pwned)",
+        "`touch pwned`",
+        "a && touch pwned",
+        "a | touch pwned",
+        "-leading-dash",
+        "quote'single",
+        "quote\"double",
+        "glob*?[ab]",
+        "dollar$HOME",
+        "new\nline",
+        "tab\there",
+        "back\\slash",
+        "x; touch pwned #.gz",
+        "ends.with.gz",
+        "ends.with.g",
+    ]
+    .iter()
+    .map(OsString::from)
+    .collect();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        names.push(OsString::from_vec(b"not-utf8-\xff\xfe".to_vec()));
+    }
+    names
+}
+
+// a fresh directory with a hostile name of its own
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "hitscanner {} $(touch pwned) {}",
+        test,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn write(dir: &Path, name: &OsString, content: &str, gzip: bool) -> PathBuf {
+    let path = dir.join(name);
+    if gzip {
+        let mut gz = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        gz.write_all(content.as_bytes()).unwrap();
+        gz.finish().unwrap();
+    } else {
+        fs::write(&path, content).unwrap();
+    }
+    path
+}
+
+fn run(dir: &Path, bin: &str, args: &[&std::ffi::OsStr]) -> Output {
+    let out = Command::new(bin).args(args).current_dir(dir).output().unwrap();
+    assert!(
+        out.status.success(),
+        "{} {:?} failed: {}",
+        bin,
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out
+}
+
+fn assert_safe(dir: &Path) {
+    assert!(!dir.join("pwned").exists(), "a command ran in {}", dir.display());
+    assert!(!Path::new("pwned").exists(), "a command ran in the cwd");
+}
+
+#[test]
+fn trace2link_reads_hostile_names() {
+    let dir = scratch("trace2link");
+    for name in names() {
+        for gzip in [false, true] {
+            let path = write(&dir, &name, TRACE, gzip);
+            let out = run(&dir, env!("CARGO_BIN_EXE_trace2link"), &[path.as_os_str()]);
+            let expected = header("trace2link") + LINKS;
+            assert_eq!(String::from_utf8_lossy(&out.stdout), expected, "{:?}", path);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+    assert_safe(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn trace2link_writes_hostile_prefix() {
+    let dir = scratch("trace2link-prefix");
+    for name in names() {
+        let path = write(&dir, &name, TRACE, false);
+        let mut prefix = dir.join(&name).into_os_string();
+        prefix.push(" $(touch pwned)-");
+        run(
+            &dir,
+            env!("CARGO_BIN_EXE_trace2link"),
+            &["-z".as_ref(), "-p".as_ref(), &prefix, path.as_os_str()],
+        );
+        let mut combined = prefix.clone();
+        combined.push("traceroute.links.gz");
+        let out = run(&dir, env!("CARGO_BIN_EXE_link2iface"), &[&combined]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "10.0.0.1\n10.0.0.2\n", "{:?}", combined);
+        let mut single = prefix.clone();
+        single.push(path.file_name().unwrap());
+        single.push(".links.gz");
+        assert!(Path::new(&single).exists(), "{:?}", single);
+    }
+    assert_safe(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn linkmerge_reads_hostile_names() {
+    let dir = scratch("linkmerge");
+    // a third of the files are in the legacy format, without a header
+    let headed = header("trace2link") + LINKS;
+    let paths: Vec<PathBuf> = names()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| match i % 3 {
+            0 => write(&dir, name, LINKS, true),
+            1 => write(&dir, name, &headed, false),
+            _ => write(&dir, name, &headed, true),
+        })
+        .collect();
+    let args: Vec<&std::ffi::OsStr> = paths.iter().map(|p| p.as_os_str()).collect();
+    let out = run(&dir, env!("CARGO_BIN_EXE_linkmerge"), &args);
+    let n = paths.len();
+    let expected = header("linkmerge")
+        + &format!(
+            "10.0.0.1 10.0.0.2 N 0 0.500 {} 1 192.0.2.1 1677196800 1677196800\n\
+             10.0.0.2 10.0.0.3 Y 0 0.500 {} 2 192.0.2.1 1677196800 1677196800\n",
+            n, n
+        );
+    assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+    assert_safe(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dbmerge_and_iplabel_read_hostile_names() {
+    let dir = scratch("dbmerge");
+    for name in names() {
+        let path = write(&dir, &name, DB, true);
+        let out = run(&dir, env!("CARGO_BIN_EXE_dbmerge"), &[path.as_os_str()]);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), MERGED, "{:?}", path);
+        let mut merged = name.clone();
+        merged.push(".merged");
+        let path = write(&dir, &merged, MERGED, true);
+        let mut child = Command::new(env!("CARGO_BIN_EXE_iplabel"))
+            .arg("-g")
+            .arg(&path)
+            .current_dir(&dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"10.0.0.1\n").unwrap();
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success(), "{:?}", path);
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "10.0.0.1 0,CN\n");
+    }
+    assert_safe(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn trace2mat_reads_hostile_names() {
+    let dir = scratch("trace2mat");
+    for name in names() {
+        let mut db = name.clone();
+        db.push(".db");
+        let mut ifaces = name.clone();
+        ifaces.push(".ifaces");
+        let db = write(&dir, &db, MERGED, false);
+        let ifaces = write(&dir, &ifaces, "10.0.0.1\n10.0.0.2\n", true);
+        let trace = write(&dir, &name, TRACE, true);
+        run(
+            &dir,
+            env!("CARGO_BIN_EXE_trace2mat"),
+            &[
+                "-g".as_ref(),
+                db.as_os_str(),
+                "-i".as_ref(),
+                ifaces.as_os_str(),
+                "-a".as_ref(),
+                "CN".as_ref(),
+                trace.as_os_str(),
+            ],
+        );
+        let rows = fs::read_to_string(dir.join("rows.csv")).unwrap();
+        let mut lines = rows.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("#rows ") && header.ends_with(" dbs=1 names=db0"), "{:?}: {}", trace, header);
+        assert!(lines.next().unwrap().starts_with("0,10.0.0.3,1,"), "{:?}: {}", trace, rows);
+    }
+    assert_safe(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+}
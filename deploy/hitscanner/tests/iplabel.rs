@@ -0,0 +1,47 @@
+// iplabel --on-error: an address of a family without a -g or -G db, a line
+// that isn't UTF-8 and an unmatched address are rejected like a malformed one.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hitscanner-iplabel {} {}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    // 10.0.0.0/8 only
+    fs::write(dir.join("merged.db"), "167772160,184549375,0,CN,1,HK\n").unwrap();
+    dir
+}
+
+fn iplabel(dir: &Path, on_error: &str, input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_iplabel"))
+        .args(["-g", "merged.db", "--on-error", on_error])
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn missing_family_db_follows_on_error() {
+    let dir = scratch("family");
+    let input = b"10.0.0.1\n2001:db8::1\n\xff\n192.0.2.1\n10.0.0.2\n";
+    let out = iplabel(&dir, "fail", input);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("-:2: invalid address line: no IPv6 database given, see -G"), "{}", stderr);
+    let out = iplabel(&dir, "quarantine", input);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(String::from_utf8_lossy(&out.stdout), "10.0.0.1 0,CN,1,HK\n10.0.0.2 0,CN,1,HK\n");
+    assert!(String::from_utf8_lossy(&out.stderr).contains("-: 3 rejected"));
+    let quarantined = fs::read_to_string(dir.join("iplabel.rejected")).unwrap();
+    let raw: Vec<&str> = quarantined.lines().filter(|l| !l.starts_with('#')).collect();
+    assert_eq!(raw, ["2001:db8::1", "\u{fffd}", "192.0.2.1"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
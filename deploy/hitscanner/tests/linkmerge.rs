@@ -0,0 +1,150 @@
+// linkmerge to the typed formats: the parquet/arrow schema must hold every
+// link of the text inputs, IPv4 and IPv6 in any order, with UInt32 addresses
+// if there are only IPv4 ones, and the optional columns of the #links header
+// even without a single link. A legacy file
+// without a header is read with the same 10 to 12 fields as link::Link.
+// The sorted runs of --sort are removed at the end, after an error too.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use arrow::datatypes::DataType;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hitscanner-linkmerge {} {}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn linkmerge(dir: &Path, args: &[&str]) -> Output {
+    let out = Command::new(env!("CARGO_BIN_EXE_linkmerge"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        out.status.success(),
+        "linkmerge {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    out
+}
+
+// more IPv4 links than a record batch holds, so the IPv6 ones come after the first batch
+fn ipv4_links() -> String {
+    let mut links: Vec<String> = (0..10000)
+        .map(|i| {
+            format!(
+                "10.{}.{}.1 10.255.0.1 N 0 0.500 1 1 192.0.2.1 1677196800 1677196800 192.0.2.1=1\n",
+                i / 250,
+                i % 250
+            )
+        })
+        .collect();
+    // sorted by (in, out) as linkmerge expects
+    links.sort();
+    links.concat()
+}
+
+const IPV6_LINKS: &str = "\
+2001:db8::1 2001:db8::2 N 0 0.500 1 1 2001:db8::ff 1677196800 1677196800 2001:db8::ff=1
+2001:db8::2 2001:db8::3 Y 0 0.500 1 2 2001:db8::ff 1677196800 1677196800 2001:db8::ff=1
+";
+
+#[test]
+fn mixed_ipv4_ipv6_text_to_typed() {
+    let dir = scratch("mixed");
+    fs::write(dir.join("v4.links"), ipv4_links()).unwrap();
+    fs::write(dir.join("v6.links"), IPV6_LINKS).unwrap();
+    let text = linkmerge(&dir, &["v4.links", "v6.links"]).stdout;
+    for format in ["parquet", "arrow"] {
+        let typed = format!("all.{}", format);
+        let out = linkmerge(&dir, &["--format", format, "v4.links", "v6.links"]);
+        fs::write(dir.join(&typed), out.stdout).unwrap();
+        // back to text, the same links
+        let back = linkmerge(&dir, &[&typed]).stdout;
+        assert_eq!(String::from_utf8_lossy(&back), String::from_utf8_lossy(&text), "{}", format);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ipv4_text_to_uint32_columns() {
+    let dir = scratch("ipv4");
+    fs::write(dir.join("v4.links"), ipv4_links()).unwrap();
+    fs::write(dir.join("v6.links"), IPV6_LINKS).unwrap();
+    let runs = [
+        (&["v4.links"][..], DataType::UInt32),
+        (&["v4.links", "v6.links"][..], DataType::FixedSizeBinary(16)),
+    ];
+    for (inputs, ip) in runs {
+        let out = linkmerge(&dir, &[&["--format", "parquet"], inputs].concat());
+        fs::write(dir.join("links.parquet"), out.stdout).unwrap();
+        let file = fs::File::open(dir.join("links.parquet")).unwrap();
+        let schema = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().schema().clone();
+        for column in ["in", "out", "monitor"] {
+            assert_eq!(schema.field_with_name(column).unwrap().data_type(), &ip, "{:?}", inputs);
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_typed_output_has_header_columns() {
+    let dir = scratch("empty");
+    let header = "#links version=1 tool=trace2link/0.1.0 \
+                  columns=in,out,is_dest,star,delay,freq,ttl,monitor,firstseen,lastseen,monitors \
+                  monitors=192.0.2.1 window=1677196800-1677196800 delay=min\n";
+    fs::write(dir.join("empty.links"), header).unwrap();
+    let out = linkmerge(&dir, &["-M", "--format", "parquet", "empty.links"]);
+    fs::write(dir.join("empty.parquet"), out.stdout).unwrap();
+    let file = fs::File::open(dir.join("empty.parquet")).unwrap();
+    let schema = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().schema().clone();
+    assert!(schema.field_with_name("monitors").is_ok(), "{:?}", schema);
+    let back = linkmerge(&dir, &["-M", "empty.parquet"]).stdout;
+    let back = String::from_utf8_lossy(&back);
+    assert!(back.contains(" columns=in,out,is_dest,star,delay,freq,ttl,monitor,firstseen,lastseen,monitors "), "{}", back);
+    assert_eq!(back.lines().count(), 1, "{}", back);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn legacy_twelve_fields() {
+    let dir = scratch("legacy");
+    let links = "\
+10.0.0.1 10.0.0.2 N 0 0.500 1 1 192.0.2.1 1677196800 1677196800 192.0.2.1=1 1
+10.0.0.2 10.0.0.3 Y 0 0.500 1 2 192.0.2.1 1677196800 1677196800 192.0.2.1=1 0
+";
+    fs::write(dir.join("legacy.links"), links).unwrap();
+    let out = linkmerge(&dir, &["-M", "--no-header", "legacy.links"]).stdout;
+    let expected: String = links.lines().map(|l| format!("{}\n", l.rsplit_once(' ').unwrap().0)).collect();
+    assert_eq!(String::from_utf8_lossy(&out), expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn sorted_runs_are_removed() {
+    let dir = scratch("runs");
+    fs::create_dir_all(dir.join("tmp")).unwrap();
+    let mut links: Vec<String> = ipv4_links().lines().map(String::from).collect();
+    links.reverse();
+    fs::write(dir.join("a.links"), links.join("\n") + "\n").unwrap();
+    fs::write(dir.join("b.links"), format!("{}\nnot a link\n", links[0])).unwrap();
+    linkmerge(&dir, &["-s", "-T", "tmp", "a.links", "b.links", "--on-error", "skip"]);
+    assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+    // b.links fails after a.links is cut into runs
+    let out = Command::new(env!("CARGO_BIN_EXE_linkmerge"))
+        .args(["-s", "-T", "tmp", "a.links", "b.links"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.starts_with("Error: b.links:2: invalid link line: "), "{}", stderr);
+    assert_eq!(fs::read_dir(dir.join("tmp")).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,125 @@
+// trace2link -p writes one output file per input: two inputs of the same file
+// name, or an input named traceroute, must fail before anything is written
+// over, and an unreadable input fails the run, with -j as without.
+// --loops=flag keeps every link of a looping trace, --anon ones included.
+// --no-header fails with the columns the legacy format can't read back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const TRACE: &str = "\
+traceroute from 192.0.2.1 to 10.0.0.3 1677196800
+ 1  10.0.0.1  1.000 ms
+ 2  10.0.0.2  2.000 ms
+ 3  10.0.0.3  3.000 ms
+";
+
+// 10.0.0.2 repeats, then the trace ends in stars
+const LOOP: &str = "\
+traceroute from 192.0.2.1 to 10.0.0.9 1677196800
+ 1  10.0.0.1  1.000 ms
+ 2  10.0.0.2  2.000 ms
+ 3  10.0.0.3  3.000 ms
+ 4  10.0.0.2  4.000 ms
+ 5  *
+ 6  *
+";
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hitscanner-trace2link {} {}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("a")).unwrap();
+    fs::create_dir_all(dir.join("b")).unwrap();
+    fs::create_dir_all(dir.join("out")).unwrap();
+    dir
+}
+
+fn trace2link(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_trace2link"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+fn assert_fails(out: &Output, msg: &str) {
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains(msg), "{}", stderr);
+}
+
+#[test]
+fn same_file_names_fail() {
+    let dir = scratch("same");
+    fs::write(dir.join("a/x"), TRACE).unwrap();
+    fs::write(dir.join("b/x"), TRACE).unwrap();
+    for jobs in ["1", "2"] {
+        let out = trace2link(&dir, &["-j", jobs, "-p", "out/", "a/x", "b/x"]);
+        assert_fails(&out, "a/x and b/x would write the same output file x");
+        assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    }
+    // in other directories of the prefix, they are fine
+    let out = trace2link(&dir, &["-p", "out/", "a/x"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn input_named_traceroute_fails() {
+    let dir = scratch("traceroute");
+    fs::write(dir.join("a/traceroute"), TRACE).unwrap();
+    let out = trace2link(&dir, &["-p", "out/", "a/traceroute"]);
+    assert_fails(&out, "would overwrite the combined traceroute links");
+    assert_eq!(fs::read_dir(dir.join("out")).unwrap().count(), 0);
+    // without -p there are no per-input files
+    let out = trace2link(&dir, &["a/traceroute"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unreadable_input_fails_in_parallel() {
+    let dir = scratch("parallel");
+    for i in 0..8 {
+        fs::write(dir.join(format!("a/{}", i)), TRACE).unwrap();
+    }
+    let mut args = vec!["-j", "4", "-p", "out/", "a/0", "a/1", "a/2", "a/missing"];
+    args.extend(["a/4", "a/5", "a/6", "a/7"]);
+    let out = trace2link(&dir, &args);
+    assert_fails(&out, "a/missing");
+    assert!(!dir.join("out/traceroute.links").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flagged_loop_keeps_last_hop() {
+    let dir = scratch("loop");
+    fs::write(dir.join("loop"), LOOP).unwrap();
+    let last = "10.0.0.2 *end_10.0.0.9 N 2 0.000 1 4 192.0.2.1 1677196800 1677196800";
+    let out = trace2link(&dir, &["--anon", "--loops=flag", "loop"]);
+    let links = String::from_utf8_lossy(&out.stdout);
+    assert!(links.contains(" looping=1 loop_links=2"), "{}", links);
+    assert!(links.lines().any(|l| l == format!("{} 1", last)), "{}", links);
+    for policy in ["drop", "truncate"] {
+        let out = trace2link(&dir, &["--anon", "--loops", policy, "loop"]);
+        let links = String::from_utf8_lossy(&out.stdout);
+        assert!(!links.contains("*end_"), "{}: {}", policy, links);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn legacy_format_without_extra_columns() {
+    let dir = scratch("legacy");
+    fs::write(dir.join("trace"), TRACE).unwrap();
+    for option in ["--delay=median", "--rtt", "--mpls"] {
+        let out = trace2link(&dir, &["--no-header", option, "trace"]);
+        let name = option.split('=').next().unwrap();
+        assert_fails(&out, &format!("--no-header can't be used with {}", name));
+    }
+    // the monitors and loops columns are legacy ones
+    let out = trace2link(&dir, &["--no-header", "--delay=min", "-M", "--loops=flag", "trace"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    fs::remove_dir_all(&dir).unwrap();
+}
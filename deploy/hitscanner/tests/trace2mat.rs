@@ -0,0 +1,177 @@
+// trace2mat --aliases: a router is a column if any of its interfaces passes -i,
+// -a and --asn, whichever of them a trace traverses, and its cols.csv line is
+// labelled by the lowest such interface, not by its router ID.
+// The -a list is written to the space-separated rows.csv header without spaces.
+// A trace with an address the merged db doesn't cover is rejected, see --on-error.
+// A repeated trace repeats its mat.csv lines, unless --counts or --row-granularity
+// aggregate them into one cell.
+// An output that can't be written is an error, and leaves no temporary file.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// 10.0.0.1 to 10.0.0.3 in CN and AS4134, the rest in AU and AS1221
+const DB: &str = "\
+0,167772163,0,CN
+167772164,4294967295,0,AU
+";
+
+const PFX2AS: &str = "\
+10.0.0.0\t30\t4134
+10.0.0.4\t30\t1221
+";
+
+// router 10.0.0.2 has an interface in each
+const ALIASES: &str = "\
+# iffinder
+10.0.0.2 10.0.0.6
+";
+
+const IFACES: &str = "\
+10.0.0.1
+10.0.0.2
+10.0.0.5
+10.0.0.6
+";
+
+// the first trace traverses the router through 10.0.0.6, the second through 10.0.0.2
+const TRACES: &str = "\
+traceroute from 192.0.2.1 to 10.0.0.9 1677196800
+ 1  10.0.0.1  1.000 ms
+ 2  10.0.0.6  2.000 ms
+ 3  10.0.0.9  3.000 ms
+traceroute from 192.0.2.1 to 10.0.0.10 1677196800
+ 1  10.0.0.2  1.000 ms
+ 2  10.0.0.10  2.000 ms
+";
+
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hitscanner-trace2mat {} {}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (name, content) in [
+        ("merged.db", DB),
+        ("routeviews.pfx2as", PFX2AS),
+        ("aliases", ALIASES),
+        ("ifaces", IFACES),
+        ("traces", TRACES),
+    ] {
+        fs::write(dir.join(name), content).unwrap();
+    }
+    dir
+}
+
+// the cols.csv and mat.csv lines of a run with the area and ASN filters
+fn trace2mat(dir: &Path, area: &str, asn: &str) -> (Vec<String>, Vec<String>) {
+    let out = Command::new(env!("CARGO_BIN_EXE_trace2mat"))
+        .args(["-g", "merged.db", "-b", "routeviews.pfx2as", "-i", "ifaces", "--aliases", "aliases"])
+        .args(["-a", area, "--asn", asn, "traces"])
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let lines = |name: &str| {
+        let text = fs::read_to_string(dir.join(name)).unwrap();
+        text.lines().map(String::from).collect()
+    };
+    (lines("cols.csv"), lines("mat.csv"))
+}
+
+#[test]
+fn router_qualifies_by_any_interface() {
+    let dir = scratch("aliases");
+    // only 10.0.0.6 qualifies, the router is labelled by it
+    let (cols, mat) = trace2mat(&dir, "AU", "1221");
+    assert_eq!(cols, ["0,10.0.0.2,1221,10.0.0.2;10.0.0.6"]);
+    // both destinations traverse the router, through either interface
+    assert_eq!(mat, ["0,0", "1,0"]);
+    // 10.0.0.2 qualifies, and labels it
+    let (mut cols, _) = trace2mat(&dir, "CN", "4134");
+    cols.sort();
+    assert_eq!(cols, ["0,10.0.0.1,4134,10.0.0.1", "1,10.0.0.2,4134,10.0.0.2;10.0.0.6"]);
+    // neither does
+    let (cols, mat) = trace2mat(&dir, "AU", "4134");
+    assert!(cols.is_empty() && mat.is_empty(), "{:?} {:?}", cols, mat);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn area_header_without_spaces() {
+    let dir = scratch("area");
+    trace2mat(&dir, " au , cn", "1221,4134");
+    let rows = fs::read_to_string(dir.join("rows.csv")).unwrap();
+    let header = rows.lines().next().unwrap();
+    assert!(header.contains(" area=AU,CN votes=1 "), "{}", header);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn uncovered_address_rejects_its_trace() {
+    let dir = scratch("uncovered");
+    // no range for 10.0.0.6
+    fs::write(dir.join("merged.db"), "0,167772165,0,CN\n167772167,4294967295,0,AU\n").unwrap();
+    let run = |on_error: &str| {
+        Command::new(env!("CARGO_BIN_EXE_trace2mat"))
+            .args(["-g", "merged.db", "-i", "ifaces", "-a", "CN,AU", "--on-error", on_error, "traces"])
+            .current_dir(&dir)
+            .output()
+            .unwrap()
+    };
+    let out = run("fail");
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.contains("merged.db:0: invalid geo db line: no range covers 10.0.0.6"), "{}", stderr);
+    // the other trace makes the matrix, the address is quarantined
+    let out = run("quarantine");
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let rows = fs::read_to_string(dir.join("rows.csv")).unwrap();
+    assert_eq!(rows.lines().skip(1).collect::<Vec<_>>(), ["0,10.0.0.10,1,10.0.0.8/29"]);
+    let quarantined = fs::read_to_string(dir.join("trace2mat.rejected")).unwrap();
+    assert!(quarantined.ends_with("\n10.0.0.6\n"), "{}", quarantined);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn repeated_trace_aggregates_only_with_counts() {
+    let dir = scratch("repeated");
+    fs::write(dir.join("traces"), TRACES.repeat(2)).unwrap();
+    let run = |extra: &[&str]| {
+        let out = Command::new(env!("CARGO_BIN_EXE_trace2mat"))
+            .args(["-g", "merged.db", "-i", "ifaces", "-a", "CN,AU"])
+            .args(extra)
+            .arg("traces")
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        let text = fs::read_to_string(dir.join("mat.csv")).unwrap();
+        text.lines().map(String::from).collect::<Vec<_>>()
+    };
+    assert_eq!(run(&[]), ["0,0", "0,1", "0,0", "0,1", "1,2", "1,2"]);
+    assert_eq!(run(&["--counts"]), ["0,0,1", "0,1,1", "1,2,1"]);
+    assert_eq!(run(&["--row-granularity", "/24"]), ["0,0", "0,1", "0,2"]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unwritable_output_is_an_error() {
+    let dir = scratch("unwritable");
+    // the rename onto a directory fails
+    fs::create_dir_all(dir.join("out/mat.csv")).unwrap();
+    let out = Command::new(env!("CARGO_BIN_EXE_trace2mat"))
+        .args(["-g", "merged.db", "-i", "ifaces", "-a", "CN,AU", "-o", "out", "traces"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(out.status.code(), Some(1), "{}", stderr);
+    assert!(stderr.starts_with("Error: can't write out/mat.csv: "), "{}", stderr);
+    let mut files: Vec<String> = fs::read_dir(dir.join("out"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    files.sort();
+    assert_eq!(files, ["mat.csv", "rows.csv"]);
+    fs::remove_dir_all(&dir).unwrap();
+}